@@ -0,0 +1,198 @@
+// Refers to one spawn of a pooled bullet.
+// Bullets are reused, so the generation of the slot is bumped each time a
+// bullet returns to the dead pool, invalidating every handle to the old spawn
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BulletHandle {
+    // Index of the bullet within every pool, fixed once instanced
+    pub slot: u32,
+    pub generation: u32,
+}
+
+impl BulletHandle {
+    // Generations wrap within 31 bits so ids stay positive
+    pub fn next_generation(generation: u32) -> u32 {
+        generation.wrapping_add(1) & 0x7fff_ffff
+    }
+
+    // Packed into one integer for GDScript, never negative
+    pub fn to_id(self) -> i64 {
+        ((self.generation as i64) << 32) | self.slot as i64
+    }
+
+    // Negative ids, as returned for dropped spawns, don't refer to any bullet
+    pub fn from_id(id: i64) -> Option<BulletHandle> {
+        if id < 0 {
+            return None;
+        }
+        Some(BulletHandle {
+            slot: (id & 0xffff_ffff) as u32,
+            generation: (id >> 32) as u32,
+        })
+    }
+}
+
+// Shorthand for handles in tests
+#[cfg(test)]
+pub fn handle(slot: u32, generation: u32) -> BulletHandle {
+    BulletHandle { slot, generation }
+}
+
+// Anything referring to one spawn of a pooled bullet
+pub trait Handled {
+    fn handle(&self) -> BulletHandle;
+}
+
+impl Handled for BulletHandle {
+    fn handle(&self) -> BulletHandle {
+        *self
+    }
+}
+
+// Index in a list of live bullets of each slot of one bullet type, starting at
+// `first_slot`. Bullets are added and removed through it to keep it up to date.
+// Entries of bullets no longer alive are left stale, lookups check the handle
+#[derive(Default)]
+pub struct AliveIndex {
+    indexes: Vec<usize>,
+    first_slot: u32,
+}
+
+impl AliveIndex {
+    pub fn new(first_slot: u32, slots: usize) -> AliveIndex {
+        AliveIndex {
+            indexes: vec![0; slots],
+            first_slot,
+        }
+    }
+
+    pub fn push<T: Handled>(&mut self, alive: &mut Vec<T>, bullet: T) {
+        let slot = (bullet.handle().slot - self.first_slot) as usize;
+        self.indexes[slot] = alive.len();
+        alive.push(bullet);
+    }
+
+    // Removes the bullet at `i`, moving the last one into its place
+    pub fn swap_remove<T: Handled>(&mut self, alive: &mut Vec<T>, i: usize) -> T {
+        let bullet = alive.swap_remove(i);
+        if let Some(moved) = alive.get(i) {
+            self.indexes[(moved.handle().slot - self.first_slot) as usize] = i;
+        }
+        bullet
+    }
+
+    // Index in `alive` of the bullet a handle refers to
+    pub fn position<T: Handled>(&self, alive: &[T], handle: BulletHandle) -> Option<usize> {
+        let slot = handle.slot.checked_sub(self.first_slot)?;
+        let i = *self.indexes.get(slot as usize)?;
+        match alive.get(i) {
+            Some(bullet) if bullet.handle() == handle => Some(i),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_roundtrip() {
+        let handles = [
+            BulletHandle {
+                slot: 0,
+                generation: 0,
+            },
+            BulletHandle {
+                slot: 517,
+                generation: 3,
+            },
+            BulletHandle {
+                slot: u32::MAX,
+                generation: 0x7fff_ffff,
+            },
+        ];
+        for handle in handles {
+            assert!(handle.to_id() >= 0);
+            assert_eq!(BulletHandle::from_id(handle.to_id()), Some(handle));
+        }
+        assert_eq!(BulletHandle::from_id(-1), None);
+    }
+
+    #[test]
+    fn killed_bullets_invalidate_their_handles() {
+        let old = BulletHandle {
+            slot: 12,
+            generation: 4,
+        };
+        // The same slot spawned again after returning to the dead pool
+        let respawned = BulletHandle {
+            slot: old.slot,
+            generation: BulletHandle::next_generation(old.generation),
+        };
+        assert_ne!(old, respawned);
+        assert_ne!(BulletHandle::from_id(old.to_id()), Some(respawned));
+
+        // Wrapping around keeps the ids positive
+        let last = BulletHandle {
+            slot: 12,
+            generation: 0x7fff_ffff,
+        };
+        let wrapped = BulletHandle {
+            slot: last.slot,
+            generation: BulletHandle::next_generation(last.generation),
+        };
+        assert_eq!(wrapped.generation, 0);
+        assert!(wrapped.to_id() >= 0);
+        assert_ne!(last, wrapped);
+    }
+
+    // Every live bullet can be found at the index it is stored at
+    fn assert_consistent(index: &AliveIndex, alive: &[BulletHandle]) {
+        for (i, bullet) in alive.iter().enumerate() {
+            assert_eq!(index.position(alive, *bullet), Some(i));
+        }
+    }
+
+    #[test]
+    fn alive_index_follows_swap_removes() {
+        // Slots 10 to 14 belong to this bullet type
+        let mut index = AliveIndex::new(10, 5);
+        let mut alive = vec![];
+        for slot in 10..15 {
+            index.push(&mut alive, handle(slot, 0));
+        }
+        assert_consistent(&index, &alive);
+
+        // Removing from the middle moves the last bullet into its place
+        let removed = index.swap_remove(&mut alive, 1);
+        assert_eq!(removed, handle(11, 0));
+        assert_eq!(alive[1], handle(14, 0));
+        assert_eq!(index.position(&alive, removed), None);
+        assert_consistent(&index, &alive);
+
+        // Removing the last bullet moves nothing
+        let removed = index.swap_remove(&mut alive, alive.len() - 1);
+        assert_eq!(removed, handle(13, 0));
+        assert_eq!(index.position(&alive, removed), None);
+        assert_consistent(&index, &alive);
+
+        // The slot spawned again under a new generation
+        index.push(&mut alive, handle(11, 1));
+        assert_eq!(index.position(&alive, handle(11, 0)), None);
+        assert_consistent(&index, &alive);
+    }
+
+    #[test]
+    fn alive_index_rejects_other_slots() {
+        let mut index = AliveIndex::new(10, 2);
+        let mut alive = vec![];
+        index.push(&mut alive, handle(10, 0));
+        // Slots of other bullet types, before and after this one's
+        assert_eq!(index.position(&alive, handle(3, 0)), None);
+        assert_eq!(index.position(&alive, handle(12, 0)), None);
+
+        // Stale entries left behind by draining the list
+        alive.clear();
+        assert_eq!(index.position(&alive, handle(10, 0)), None);
+    }
+}
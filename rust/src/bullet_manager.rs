@@ -1,27 +1,339 @@
-use gdnative::api::Node2D;
+use gdnative::api::{AnimatedSprite, Line2D, Node2D};
 use gdnative::prelude::*;
 
 use std::collections::HashMap;
+use std::f32::consts::PI;
 
-use crate::encounter_manager::EncounterManager;
-use crate::player::Player;
+use crate::bullet_handle::{AliveIndex, BulletHandle, Handled};
+use crate::bullet_motion::{self, Burst, Fate, Motion, Orbit, PlayerHitbox, StepContext};
+use crate::collision;
+use crate::encounter_manager::{self, EncounterManager};
+use crate::laser::{Laser, LaserConfig, LaserPhase};
+use crate::node_paths;
+use crate::player::{self, Player};
+use crate::save_state::{
+    self, BulletManagerSnapshot, BulletSnapshot, BurstSnapshot, LaserSnapshot, OrbitSnapshot,
+};
+use crate::signals;
+use crate::time_scale::{self, TimeScale};
+
+// Per-spawn look of a bullet, so one bullet type can be used in many colours
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BulletVisual {
+    // Multiplied with the colours of the sprite
+    pub tint: Color,
+    // Only changes the sprite, the hitbox keeps the radius of the bullet type
+    pub scale: f32,
+    // Frame shown by the bullet's AnimatedSprite
+    // -1 = keep playing the animation
+    pub frame: i64,
+}
+
+impl Default for BulletVisual {
+    fn default() -> Self {
+        Self {
+            tint: Color::from_rgba(1.0, 1.0, 1.0, 1.0),
+            scale: 1.0,
+            frame: -1,
+        }
+    }
+}
+
+impl BulletVisual {
+    // Shrunk and faded out by `progress` (1.0 - 0.0) while despawning,
+    // starting from the bullet's own scale and tint
+    pub fn faded(&self, progress: f32) -> BulletVisual {
+        let tint = self.tint;
+        BulletVisual {
+            tint: Color::from_rgba(tint.r, tint.g, tint.b, tint.a * progress),
+            scale: self.scale * progress,
+            frame: self.frame,
+        }
+    }
+
+    // Frame the sprite is held on, None to keep playing its animation
+    pub fn held_frame(&self) -> Option<i64> {
+        (self.frame >= 0).then_some(self.frame)
+    }
+}
+
+// Rotation (radians) turning a bullet to face the direction it travels,
+// None when it doesn't turn or isn't moving
+fn facing(rotate: bool, velocity: Vector2) -> Option<f32> {
+    (rotate && velocity != Vector2::new(0.0, 0.0)).then(|| velocity.y.atan2(velocity.x))
+}
 
 // Storage type for tracking bullet sprites
 struct Bullet {
     node: Ref<Node2D, Shared>,
-    dx: f32,
-    dy: f32,
+    // First AnimatedSprite of the bullet's scene, if any
+    sprite: Option<Ref<AnimatedSprite, Shared>>,
+    visual: BulletVisual,
+    motion: Motion,
+    // Wether the bullet has already been counted as a graze
+    grazed: bool,
+    // Position in the pools and current spawn, see BulletHandle
+    slot: u32,
+    generation: u32,
+    // Group the bullet is commanded with, see create_group
+    group: Option<u32>,
+}
+
+impl Handled for Bullet {
+    fn handle(&self) -> BulletHandle {
+        BulletHandle {
+            slot: self.slot,
+            generation: self.generation,
+        }
+    }
+}
+
+impl Bullet {
+    fn snapshot(
+        &self,
+        kind: &str,
+        orbit: Option<OrbitSnapshot>,
+        despawn_frames: Option<u32>,
+    ) -> BulletSnapshot {
+        BulletSnapshot {
+            kind: kind.to_string(),
+            position: save_state::to_position(self.motion.position),
+            dx: self.motion.dx,
+            dy: self.motion.dy,
+            grazed: self.grazed,
+            age: self.motion.age,
+            burst: self.motion.burst.as_ref().map(|burst| BurstSnapshot {
+                frames: burst.frames,
+                kind: burst.kind.clone(),
+                count: burst.count,
+                speed: burst.speed,
+                angle: burst.angle,
+            }),
+            orbit,
+            group: self.group,
+            tint: (
+                self.visual.tint.r,
+                self.visual.tint.g,
+                self.visual.tint.b,
+                self.visual.tint.a,
+            ),
+            scale: self.visual.scale,
+            frame: self.visual.frame,
+            homing: self.motion.homing,
+            despawn_frames,
+        }
+    }
+
+    // Applies the visual, faded and shrunk by `progress` (0.0 - 1.0) while despawning
+    fn show(&self, progress: f32) {
+        let node = unsafe { self.node.assume_safe() };
+        let visual = self.visual.faded(progress);
+        node.set_scale(Vector2::new(visual.scale, visual.scale));
+        node.set_modulate(visual.tint);
+    }
+
+    fn set_visual(&mut self, visual: BulletVisual) {
+        self.visual = visual;
+        self.show(1.0);
+        if let Some(sprite) = &self.sprite {
+            let sprite = unsafe { sprite.assume_safe() };
+            match visual.held_frame() {
+                Some(frame) => {
+                    sprite.stop();
+                    sprite.set_frame(frame);
+                }
+                None if !sprite.is_playing() => sprite.play("", false),
+                None => (),
+            }
+        }
+    }
+}
+
+// Copy of a live bullet's state, as returned by queries
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BulletView {
+    pub handle: BulletHandle,
+    pub kind: &'static str,
+    pub position: Vector2,
+    // (px/sec)
+    pub velocity: Vector2,
+    pub radius: f32,
+    // Simulation time since the bullet was spawned (secs)
+    pub age: f32,
+}
+
+impl BulletView {
+    fn of(kind: &'static str, entry: &BulletEntry, bullet: &Bullet) -> BulletView {
+        BulletView {
+            handle: bullet.handle(),
+            kind,
+            position: bullet.motion.position,
+            velocity: Vector2::new(bullet.motion.dx, bullet.motion.dy),
+            radius: entry.radius as f32,
+            age: bullet.motion.age,
+        }
+    }
+
+    // Wether the bullet's position is inside the rectangle, edges included
+    pub fn in_rect(&self, rect: Rect2) -> bool {
+        let min = rect.position;
+        let max = rect.position + rect.size;
+        self.position.x >= min.x
+            && self.position.y >= min.y
+            && self.position.x <= max.x
+            && self.position.y <= max.y
+    }
+
+    // Wether the bullet touches the circle, including its own radius
+    pub fn touches_circle(&self, center: Vector2, radius: f32) -> bool {
+        self.position.distance_to(center) <= radius + self.radius
+    }
+
+    // Closest bullet to a point, the first one on ties
+    pub fn nearest(
+        bullets: impl IntoIterator<Item = BulletView>,
+        point: Vector2,
+    ) -> Option<BulletView> {
+        bullets
+            .into_iter()
+            .fold(None, |nearest: Option<BulletView>, x| match nearest {
+                Some(nearest)
+                    if nearest.position.distance_to(point) <= x.position.distance_to(point) =>
+                {
+                    Some(nearest)
+                }
+                _ => Some(x),
+            })
+    }
 }
 
 // Storage type for seperating types of bullets
 struct BulletEntry {
+    // Bullets are added and removed through the methods below to keep
+    // `alive_index` up to date, draining it entirely is fine
     alive: Vec<Bullet>,
+    alive_index: AliveIndex,
     dead: Vec<Bullet>,
+    // Cancelled bullets playing their despawn animation
+    // along with the frames remaining
+    despawning: Vec<(Bullet, u32)>,
     amount: i32,
     radius: u32,
+    // Time before the bullet despawns (msec)
+    // -1 = until it leaves the screen
+    lifetime_ms: i64,
+    // Distance past the edge of the screen before the bullet is removed
+    margin: f32,
+    // Wether the bullet turns to face the direction it travels
+    rotate: bool,
     scene: Ref<PackedScene, Shared>,
 }
 
+impl BulletEntry {
+    fn push_alive(&mut self, bullet: Bullet) {
+        self.alive_index.push(&mut self.alive, bullet);
+    }
+
+    // Removes the bullet at `i`, moving the last one into its place
+    fn swap_remove_alive(&mut self, i: usize) -> Bullet {
+        self.alive_index.swap_remove(&mut self.alive, i)
+    }
+
+    // Index in `alive` of the bullet a handle refers to
+    fn alive_position(&self, handle: BulletHandle) -> Option<usize> {
+        self.alive_index.position(&self.alive, handle)
+    }
+
+    // Starts the despawn animation, the bullet no longer collides
+    fn despawn(&mut self, bullet: Bullet, frames: u32) {
+        self.despawning.push((bullet, frames));
+    }
+
+    // Returns the bullet to the dead pool, invalidating its handles
+    fn kill(&mut self, mut bullet: Bullet) {
+        bullet.generation = BulletHandle::next_generation(bullet.generation);
+        bullet.motion.burst = None;
+        bullet.motion.orbit = None;
+        bullet.motion.homing = None;
+        bullet.group = None;
+        self.dead.push(bullet);
+    }
+}
+
+// One bullet of the ring replacing a bursting bullet
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct RingBullet {
+    velocity: Vector2,
+    group: Option<u32>,
+    visual: BulletVisual,
+}
+
+// Evenly spaced from the burst's angle, joining the group of
+// the bursting bullet and keeping its look
+fn ring(burst: &Burst, group: Option<u32>, visual: BulletVisual) -> Vec<RingBullet> {
+    let count = burst.count.max(1);
+    (0..count)
+        .map(|i| {
+            let angle = burst.angle + 2.0 * PI * i as f32 / count as f32;
+            RingBullet {
+                velocity: Vector2::new(angle.cos(), angle.sin()) * burst.speed,
+                group,
+                visual,
+            }
+        })
+        .collect()
+}
+
+// Wether a bullet in `bullet_group` belongs to the group with id `group`.
+// Bullets without a group belong to none, -1 and ids out of range match nothing
+fn in_group(bullet_group: Option<u32>, group: i64) -> bool {
+    bullet_group.is_some() && bullet_group == u32::try_from(group).ok()
+}
+
+// Orbits refer to their parent by its index in the snapshot, as handles change
+// once restored. Orbits of removed parents are released on the next tick anyway
+fn orbit_snapshot(orbit: Orbit, indexes: &HashMap<BulletHandle, usize>) -> Option<OrbitSnapshot> {
+    indexes.get(&orbit.parent).map(|parent| OrbitSnapshot {
+        parent: *parent as u64,
+        radius: orbit.radius,
+        angle: orbit.angle,
+        angular_speed: orbit.angular_speed,
+    })
+}
+
+// Orbit around the restored parent, given the handle of each restored bullet by
+// index in the snapshot. None when the parent couldn't be restored
+fn restored_orbit(orbit: &OrbitSnapshot, handles: &[Option<BulletHandle>]) -> Option<Orbit> {
+    let parent = (*handles.get(orbit.parent as usize)?)?;
+    Some(Orbit {
+        parent,
+        radius: orbit.radius,
+        angle: orbit.angle,
+        angular_speed: orbit.angular_speed,
+    })
+}
+
+// Fraction of the despawn animation left (1.0 - 0.0) with `frames` remaining
+fn despawn_progress(frames: u32, despawn_frames: u32) -> f32 {
+    frames as f32 / despawn_frames.max(1) as f32
+}
+
+// Counts down every despawn animation by a frame,
+// removing and returning the bullets that finished
+fn tick_despawning<T>(despawning: &mut Vec<(T, u32)>) -> Vec<T> {
+    let mut finished = vec![];
+    let mut i = 0;
+    while i < despawning.len() {
+        if despawning[i].1 == 0 {
+            finished.push(despawning.swap_remove(i).0);
+        } else {
+            despawning[i].1 -= 1;
+            i += 1;
+        }
+    }
+    finished
+}
+
 // Provides a list of bullet types
 // Adding to this list will add to the properties as well
 pub fn bullet_types() -> Vec<&'static str> {
@@ -32,17 +344,89 @@ pub fn bullet_types() -> Vec<&'static str> {
         "player_primary_03",
         // Orb bullets
         "orb_bullet",
+        // Items
+        "item_score",
     ]
 }
 
+// Which side a bullet type belongs to, determined by the prefix of its name
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Faction {
+    // "player_*", collides with enemies
+    Player,
+    // "item_*", collected by the player
+    Item,
+    // Anything else, collides with the player
+    Enemy,
+}
+
+impl Faction {
+    pub fn of(bullet_type: &str) -> Faction {
+        if bullet_type.starts_with("player") {
+            Faction::Player
+        } else if bullet_type.starts_with("item") {
+            Faction::Item
+        } else {
+            Faction::Enemy
+        }
+    }
+
+    // Parses the faction names used by exported methods
+    pub fn from_name(name: &str) -> Option<Faction> {
+        match name {
+            "player" => Some(Faction::Player),
+            "item" => Some(Faction::Item),
+            "enemy" => Some(Faction::Enemy),
+            _ => None,
+        }
+    }
+}
+
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
 #[register_with(Self::register)]
 pub struct BulletManager {
     bullets: HashMap<String, BulletEntry>,
+    // Bullet type of each slot, to find bullets by handle
+    slot_types: Vec<&'static str>,
+    // Id given to the next bullet group
+    next_group: u32,
+    // Enemy lasers, each with a Line2D from the pool below
+    lasers: Vec<Laser>,
+    // Line2Ds not in use by a laser
+    free_lasers: Vec<Ref<Line2D, Shared>>,
+
+    // Line2D scene instanced for the laser pool
+    #[property]
+    laser_scene: Option<Ref<PackedScene, Shared>>,
+    // Lasers that can be in play at once
+    #[property(default = 16)]
+    laser_amount: u32,
+    // Width of the line shown during a laser's warning phase
+    #[property(default = 1.0)]
+    laser_warning_width: f32,
+
+    // Nodes used for bullet collisions
+    #[property]
+    encounter_manager_path: NodePath,
+    // Time scale applied to bullet movement
+    #[property]
+    time_scale_path: NodePath,
+    time_scale: Option<TInstance<'static, TimeScale, Shared>>,
+
+    // Length of the despawn animation for cancelled bullets
+    #[property(default = 8)]
+    despawn_frames: u32,
+    // Speed at which items are pulled towards the player
+    #[property(default = 120.0)]
+    item_speed: f32,
+    // Distance outside of the player's hitbox that counts as a graze
+    #[property(default = 12.0)]
+    graze_radius: f32,
 
     enemy_manager: Option<TInstance<'static, EncounterManager, Shared>>,
-    player: Option<TInstance<'static, Player, Shared>>,
+    // Player 1 first
+    players: Vec<TInstance<'static, Player, Shared>>,
 }
 
 #[methods]
@@ -50,6 +434,13 @@ impl BulletManager {
     // Produces the list of properties needed to configure the
     // packed scene and quantity of each bullet
     fn register(builder: &ClassBuilder<Self>) {
+        // Emitted when a bullet can't be spawned as every bullet of its type is in use,
+        // with "laser" as the type when every laser is in use
+        builder
+            .signal("pool_exhausted")
+            .with_param("bullet_type", VariantType::GodotString)
+            .done();
+
         for bullet_type in bullet_types() {
             builder
                 .property(&format!("bullet_scenes/{bullet_type}"))
@@ -63,9 +454,14 @@ impl BulletManager {
                         .or_insert(BulletEntry {
                             amount: 0,
                             radius: 0,
+                            lifetime_ms: -1,
+                            margin: 0.0,
+                            rotate: false,
                             scene: v,
                             alive: vec![],
+                            alive_index: AliveIndex::default(),
                             dead: vec![],
+                            despawning: vec![],
                         });
                 })
                 .done();
@@ -103,141 +499,1181 @@ impl BulletManager {
                     }
                 })
                 .done();
+
+            builder
+                .property(&format!("bullet_lifetime_ms/{bullet_type}"))
+                .with_default(-1)
+                .with_ref_getter(move |this: &BulletManager, _owner: TRef<Node2D>| {
+                    let res = &this.bullets.get(bullet_type).unwrap().lifetime_ms;
+                    res
+                })
+                .with_setter(move |this: &mut BulletManager, _owner: TRef<Node2D>, v| {
+                    let entry = this.bullets.get_mut(&bullet_type.to_string());
+                    if entry.is_some() {
+                        (*entry.unwrap()).lifetime_ms = v;
+                    } else {
+                        godot_warn!("Attempt to set bullet lifetime for {bullet_type} without scene");
+                    }
+                })
+                .done();
+
+            builder
+                .property(&format!("bullet_margin/{bullet_type}"))
+                .with_default(0.0)
+                .with_ref_getter(move |this: &BulletManager, _owner: TRef<Node2D>| {
+                    let res = &this.bullets.get(bullet_type).unwrap().margin;
+                    res
+                })
+                .with_setter(move |this: &mut BulletManager, _owner: TRef<Node2D>, v| {
+                    let entry = this.bullets.get_mut(&bullet_type.to_string());
+                    if entry.is_some() {
+                        (*entry.unwrap()).margin = v;
+                    } else {
+                        godot_warn!("Attempt to set bullet margin for {bullet_type} without scene");
+                    }
+                })
+                .done();
+
+            builder
+                .property(&format!("bullet_rotate/{bullet_type}"))
+                .with_default(false)
+                .with_ref_getter(move |this: &BulletManager, _owner: TRef<Node2D>| {
+                    let res = &this.bullets.get(bullet_type).unwrap().rotate;
+                    res
+                })
+                .with_setter(move |this: &mut BulletManager, _owner: TRef<Node2D>, v| {
+                    let entry = this.bullets.get_mut(&bullet_type.to_string());
+                    if entry.is_some() {
+                        (*entry.unwrap()).rotate = v;
+                    } else {
+                        godot_warn!("Attempt to set bullet rotation for {bullet_type} without scene");
+                    }
+                })
+                .done();
         }
     }
 
     fn new(_owner: &Node2D) -> Self {
-        Self::default()
+        Self {
+            encounter_manager_path: NodePath::from_str("../Encounters"),
+            time_scale_path: NodePath::from_str("../Time"),
+            despawn_frames: 8,
+            item_speed: 120.0,
+            graze_radius: 12.0,
+            laser_warning_width: 1.0,
+            laser_amount: 16,
+            ..Default::default()
+        }
     }
 
     #[export]
     pub fn _ready(&mut self, owner: &Node2D) {
         // Take a refrence to the EncountersHandler to manage checking for bullet collision
         // with enemies.
-        self.enemy_manager =
-            unsafe { owner.get_node_as_instance::<EncounterManager>("../Encounters") };
-        // Take a refrence to the Player in order to manage bullet collision with the player
-        self.player = unsafe { owner.get_node_as_instance::<Player>("../Player") };
+        self.enemy_manager = node_paths::fetch_instance(
+            owner,
+            &self.encounter_manager_path,
+            "EncounterManager",
+        );
+        // Take a refrence to the Players in order to manage bullet collision with them
+        let players = encounter_manager::players(&self.enemy_manager);
+        node_paths::require(owner, self.enemy_manager.is_some() && players.is_some());
+        self.players = players.unwrap_or_default();
+        self.time_scale = node_paths::fetch_instance(owner, &self.time_scale_path, "TimeScale");
 
         // Iterate through the bullet types and initialize the bullet sprites
         for bullet_type in bullet_types() {
-            let bullet_info = self.bullets.get_mut(bullet_type).unwrap();
+            let bullet_info = match self.bullets.get_mut(bullet_type) {
+                Some(bullet_info) => bullet_info,
+                None => {
+                    godot_warn!("No scene set for bullet type {bullet_type}, skipping");
+                    continue;
+                }
+            };
 
+            let first_slot = self.slot_types.len() as u32;
+            bullet_info.alive_index =
+                AliveIndex::new(first_slot, bullet_info.amount.max(0) as usize);
             for _ in 0..bullet_info.amount {
                 // Instance a "scene" to create a bullet sprite
                 let bullet: Ref<Node2D, _> = BulletManager::instance_scene(&bullet_info.scene);
                 bullet.set_visible(false);
+                let sprite = (0..bullet.get_child_count())
+                    .filter_map(|i| bullet.get_child(i))
+                    .find_map(|child| {
+                        let child = unsafe { child.assume_safe() };
+                        child.cast::<AnimatedSprite>().map(|x| x.claim())
+                    });
 
                 let bullet = bullet.into_shared();
                 owner.add_child(bullet, false);
 
                 // Insert the bullet into the list of managed bullets
                 let bullet = Bullet {
-                    dx: 0.0,
-                    dy: 0.0,
+                    motion: Motion::new(Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)),
+                    grazed: false,
+                    slot: self.slot_types.len() as u32,
+                    generation: 0,
+                    group: None,
                     node: bullet,
+                    sprite,
+                    visual: BulletVisual::default(),
                 };
+                self.slot_types.push(bullet_type);
 
                 (*bullet_info).dead.push(bullet);
             }
         }
+
+        match &self.laser_scene {
+            Some(scene) => {
+                for _ in 0..self.laser_amount {
+                    let node: Ref<Line2D, _> = BulletManager::instance_scene(scene);
+                    node.set_visible(false);
+                    let node = node.into_shared();
+                    owner.add_child(node, false);
+                    self.free_lasers.push(node);
+                }
+            }
+            None => godot_warn!("No laser_scene set, lasers will be dropped"),
+        }
     }
 
     #[export]
-    pub fn _process(&mut self, _owner: &Node2D, deltatime: f32) {
+    pub fn _process(&mut self, owner: &Node2D, deltatime: f32) {
+        // Bullets freeze in place (including collisions and despawns) while paused
+        let deltatime = time_scale::scaled(&self.time_scale, deltatime);
+        if deltatime == 0.0 {
+            return;
+        }
+
         let enemy_manager = self.enemy_manager.as_ref().unwrap();
-        // Request the player's position to assist in collision calculations
-        let player_pos = self
-            .player
-            .as_ref()
-            .unwrap()
-            .map(|_: &Player, node: TRef<Node2D>| node.global_position())
-            .unwrap();
+        // Request the players' positions and hitboxes to assist in collision calculations
+        let (players, hitboxes) = player::live_players(&self.players);
+        let parents = self.orbit_parents(deltatime);
+        let enemy_targets = self.homing_targets();
+        let player_targets: Vec<Vector2> = hitboxes.iter().map(|x| x.position).collect();
+        // Bursting bullets, with their position, group and visual,
+        // replaced by their rings once every bullet has moved
+        let mut bursts = vec![];
         for bullet_type in bullet_types() {
-            // Check if the bullet is from the player
-            // allows collision with enemies, disallows collision with player
-            let is_player = bullet_type.starts_with("player");
+            // Player bullets collide with enemies, enemy bullets with the players
+            // and items are pulled towards the nearest player to be collected
+            let faction = Faction::of(bullet_type);
 
-            // List of indexes to transfer from living to dead
+            // List of indexes to transfer from living to dead,
+            // along with wether they play the despawn animation first
             let mut to_remove = vec![];
             // Refrence to the configuration for bullet type
-            let bullet_info = self.bullets.get_mut(bullet_type).unwrap();
+            let bullet_info = match self.bullets.get_mut(bullet_type) {
+                Some(bullet_info) => bullet_info,
+                None => continue,
+            };
 
-            for i in 0..bullet_info.alive.len() {
-                let bullet = &bullet_info.alive[i];
-                let velocity = Vector2::new(bullet.dx, bullet.dy) * deltatime;
+            let context = StepContext {
+                deltatime,
+                players: &hitboxes,
+                homing_speed: (faction == Faction::Item).then_some(self.item_speed),
+                margin: bullet_info.margin,
+                lifetime_ms: bullet_info.lifetime_ms,
+                parents: &parents,
+                // Items are pulled by `homing_speed` instead
+                targets: match faction {
+                    Faction::Player => &enemy_targets[..],
+                    Faction::Enemy => &player_targets[..],
+                    Faction::Item => &[],
+                },
+            };
+            // Move every bullet first, in parallel for large amounts, then handle
+            // what happened to each in order as that calls into Godot
+            let steps =
+                bullet_motion::integrate_all(&mut bullet_info.alive, |x| &mut x.motion, &context);
 
+            for (i, step) in steps.iter().enumerate() {
+                let bullet = &mut bullet_info.alive[i];
                 let node = unsafe { bullet.node.assume_safe() };
-
-                node.set_global_position(node.global_position() + velocity);
+                let pos = bullet.motion.position;
+                node.set_global_position(pos);
+                let velocity = Vector2::new(bullet.motion.dx, bullet.motion.dy);
+                if let Some(rotation) = facing(bullet_info.rotate, velocity) {
+                    node.set_rotation(rotation as f64);
+                }
 
                 // Check for collisions (left screen, hit player, hit enemy)
-                let pos = node.global_position();
-                if pos.x < 0.0 || pos.y < 0.0 || pos.x > 480.0 || pos.y > 270.0 {
+                // along the whole path travelled this tick, so fast bullets
+                // and long frames can't skip over a hitbox
+                let previous = step.previous;
+                let player = step.player.map(|x| &players[x]);
+                let touching_player = step.player_gap <= bullet_info.radius as f32;
+                if step.fate == Fate::OffScreen {
                     node.set_visible(false);
-                    to_remove.push(i);
-                } else if is_player {
-                    if enemy_manager
-                        .map_mut(|x: &mut EncounterManager, node: TRef<Node2D>| {
-                            // Request the Encounter to check for bullet collisions
-                            x.hit_enemy(node.as_ref(), pos, bullet_info.radius)
-                        })
-                        .unwrap()
-                    {
-                        // Push the bullet back into the queue to be reused
-                        node.set_visible(false);
-                        to_remove.push(i);
-                    }
-                } else {
-                    // Check if the player is within 4 + bullet_radius of the bullet
-                    if (player_pos.x - pos.x).powf(2.0) + (player_pos.y - pos.y).powf(2.0)
-                        <= (4.0 + bullet_info.radius as f32).powf(2.0)
-                    {
-                        if self.player
-                            .as_ref()
-                            .unwrap()
-                            .map_mut(|x, node| x.hit(node.as_ref()))
-                            .unwrap() {
-                            node.set_visible(false);
-                            to_remove.push(i);
+                    to_remove.push((i, false));
+                } else if step.fate == Fate::Burst {
+                    let burst = bullet.motion.burst.take().unwrap();
+                    bursts.push((burst, pos, bullet.group, bullet.visual));
+                    node.set_visible(false);
+                    to_remove.push((i, false));
+                } else if step.fate == Fate::Expired {
+                    to_remove.push((i, true));
+                } else if step.fate.collides() {
+                    match faction {
+                        Faction::Player => {
+                            if enemy_manager
+                                .map_mut(|x: &mut EncounterManager, node: TRef<Node2D>| {
+                                    // Request the Encounter to check for bullet collisions
+                                    x.hit_enemy(node.as_ref(), previous, pos, bullet_info.radius)
+                                })
+                                .unwrap()
+                            {
+                                // Push the bullet back into the queue to be reused
+                                node.set_visible(false);
+                                to_remove.push((i, false));
+                            }
+                        }
+                        Faction::Enemy => {
+                            // Nothing to check when far from every player
+                            let player = match player {
+                                Some(player) => player,
+                                None => continue,
+                            };
+                            let radius = bullet_info.radius as f32;
+                            if step.player_gap > radius + self.graze_radius {
+                                continue;
+                            }
+                            // Hit every player within the hitbox + bullet_radius in turn, nearest
+                            // first, until one takes the hit, so an invulnerable player doesn't
+                            // shield another
+                            let hit = touching_player
+                                && bullet_motion::players_in_reach(
+                                    &hitboxes, previous, pos, radius,
+                                )
+                                .into_iter()
+                                .any(|x| {
+                                    players[x].map_mut(|x, node| x.hit(node.as_ref())).unwrap()
+                                });
+                            if hit {
+                                node.set_visible(false);
+                                to_remove.push((i, false));
+                            } else if !bullet.grazed {
+                                // Count each bullet passing close by once
+                                bullet.grazed = true;
+                                player.map_mut(|x, node| x.graze(node.as_ref())).unwrap();
+                            }
+                        }
+                        Faction::Item => {
+                            // Collected by the nearest player
+                            if let (true, Some(player)) = (touching_player, player) {
+                                player
+                                    .map_mut(|x, node| x.collect_item(node.as_ref()))
+                                    .unwrap();
+                                node.set_visible(false);
+                                to_remove.push((i, false));
+                            }
                         }
                     }
                 }
             }
 
             // Swap bullets into the dead queue
-            for i in to_remove.iter().rev() {
+            for (i, despawn) in to_remove.iter().rev() {
                 // Use swap_remove on a backwards list of indexes
                 // to not change order as well as increase performance
                 // if many bullets are removed at once
-                bullet_info.dead.push(bullet_info.alive.swap_remove(*i));
+                let bullet = bullet_info.swap_remove_alive(*i);
+                if *despawn {
+                    bullet_info.despawn(bullet, self.despawn_frames);
+                } else {
+                    bullet_info.kill(bullet);
+                }
+            }
+
+            // Shrink and fade out cancelled and expired bullets.
+            // They are kept out of `alive`, so they never collide
+            for bullet in tick_despawning(&mut bullet_info.despawning) {
+                let node = unsafe { bullet.node.assume_safe() };
+                node.set_visible(false);
+                bullet_info.kill(bullet);
+            }
+            for (bullet, frames) in &bullet_info.despawning {
+                bullet.show(despawn_progress(*frames, self.despawn_frames));
+            }
+        }
+
+        for (burst, pos, group, visual) in bursts {
+            self.spawn_ring(owner, &burst, pos, group, visual);
+        }
+
+        self.process_lasers(&players, &hitboxes, deltatime);
+    }
+
+    // Where the parents of orbiting bullets will be after moving this tick
+    fn orbit_parents(&self, deltatime: f32) -> HashMap<BulletHandle, Vector2> {
+        let alive = || self.bullets.values().flat_map(|x| x.alive.iter());
+        if !alive().any(|x| x.motion.orbit.is_some()) {
+            return HashMap::new();
+        }
+        let motions = alive().map(|x| (x.handle(), &x.motion)).collect();
+        bullet_motion::orbit_parents(&motions, deltatime)
+    }
+
+    // Enemy positions for homing player shots, only looked up while there are any
+    fn homing_targets(&self) -> Vec<Vector2> {
+        let homing = self
+            .bullets
+            .iter()
+            .filter(|(bullet_type, _)| Faction::of(bullet_type) == Faction::Player)
+            .flat_map(|(_, x)| x.alive.iter())
+            .any(|x| x.motion.homing.is_some());
+        match &self.enemy_manager {
+            Some(enemy_manager) if homing => enemy_manager
+                .map(|x: &EncounterManager, _node: TRef<Node2D>| x.enemy_positions())
+                .unwrap(),
+            _ => vec![],
+        }
+    }
+
+    // Evenly spaced ring of bullets replacing a bursting bullet,
+    // joining the group and taking the look of the bullet
+    fn spawn_ring(
+        &mut self,
+        owner: &Node2D,
+        burst: &Burst,
+        pos: Vector2,
+        group: Option<u32>,
+        visual: BulletVisual,
+    ) {
+        if !self.bullets.contains_key(&burst.kind) {
+            godot_warn!("Unknown bullet type {} for burst", burst.kind);
+            return;
+        }
+        for bullet in ring(burst, group, visual) {
+            let handle = self.spawn_styled(owner, &burst.kind, pos, bullet.velocity, bullet.visual);
+            if let Some(handle) = handle {
+                self.bullet_mut(handle).unwrap().group = bullet.group;
+            }
+        }
+    }
+
+    // Moves and animates the lasers, hitting the players with active ones
+    fn process_lasers(
+        &mut self,
+        players: &[TInstance<'static, Player, Shared>],
+        hitboxes: &[PlayerHitbox],
+        deltatime: f32,
+    ) {
+        let mut i = 0;
+        while i < self.lasers.len() {
+            let laser = &mut self.lasers[i];
+            laser.age += deltatime;
+            let node = unsafe { laser.node.assume_safe() };
+
+            let phase = laser.phase();
+            if phase == LaserPhase::Finished {
+                node.set_visible(false);
+                let laser = self.lasers.swap_remove(i);
+                self.free_lasers.push(laser.node);
+                continue;
+            }
+
+            let points = laser.points();
+            let width = laser.width(self.laser_warning_width);
+            BulletManager::draw_laser(&node, &points, width, phase);
+
+            // Lasers stay in play after hitting a player
+            if phase == LaserPhase::Active {
+                for (player, hitbox) in players.iter().zip(hitboxes) {
+                    if collision::polyline_touches_circle(
+                        &points,
+                        hitbox.position,
+                        hitbox.radius + width / 2.0,
+                    ) {
+                        player.map_mut(|x, node| x.hit(node.as_ref())).unwrap();
+                    }
+                }
             }
+            i += 1;
         }
     }
 
-    // Called by Player and GenericEnemy to spawn a bullet
+    fn draw_laser(node: &Line2D, points: &[Vector2], width: f32, phase: LaserPhase) {
+        // Points are global, so keep the line at the origin
+        node.set_global_position(Vector2::new(0.0, 0.0));
+        node.set_points(Vector2Array::from_vec(points.to_vec()));
+        node.set_width(width as f64);
+        let alpha = if phase == LaserPhase::Warning { 0.35 } else { 1.0 };
+        node.set_modulate(Color::from_rgba(1.0, 1.0, 1.0, alpha));
+    }
+
+    // Called by GenericEnemy to fire a laser, see LaserConfig
+    pub fn spawn_laser(&mut self, owner: &Node2D, config: LaserConfig) {
+        let node = match self.free_lasers.pop() {
+            Some(node) => node,
+            None => {
+                // Drop the laser, laser_amount needs raising
+                signals::emit_deferred(owner, "pool_exhausted", &["laser".to_variant()]);
+                return;
+            }
+        };
+
+        let laser = Laser {
+            config,
+            node,
+            age: 0.0,
+        };
+        let points = laser.points();
+        let width = laser.width(self.laser_warning_width);
+        let node = unsafe { laser.node.assume_safe() };
+        BulletManager::draw_laser(&node, &points, width, laser.phase());
+        node.set_visible(true);
+        self.lasers.push(laser);
+    }
+
+    // Fires a straight, optionally rotating, laser from GDScript
     #[export]
-    pub fn spawn_bullet(
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_laser_line(
         &mut self,
+        owner: &Node2D,
+        x: f32,
+        y: f32,
+        angle: f32,
+        length: f32,
+        width: f32,
+        warning_ms: i64,
+        active_ms: i64,
+        rotation_speed: f32,
+    ) {
+        self.spawn_laser(
+            owner,
+            LaserConfig {
+                origin: Vector2::new(x, y),
+                angle,
+                length,
+                width,
+                warning_ms,
+                active_ms,
+                rotation_speed,
+                ..Default::default()
+            },
+        );
+    }
+
+    // Removes every laser from play, returning their nodes to the pool
+    fn clear_lasers(&mut self) {
+        for laser in self.lasers.drain(..) {
+            unsafe { laser.node.assume_safe() }.set_visible(false);
+            self.free_lasers.push(laser.node);
+        }
+    }
+
+    // Number of bullets in play for a faction
+    pub fn live_count(&self, faction: Faction) -> usize {
+        self.bullets
+            .iter()
+            .filter(|(bullet_type, _)| Faction::of(bullet_type) == faction)
+            .map(|(_, bullet_info)| bullet_info.alive.len())
+            .sum()
+    }
+
+    // Exposes `live_count` using the faction names "player", "enemy" and "item"
+    #[export]
+    pub fn live_bullets(&self, _owner: &Node2D, faction: String) -> u32 {
+        match Faction::from_name(&faction) {
+            Some(faction) => self.live_count(faction) as u32,
+            None => {
+                godot_warn!("Unknown bullet faction {faction}");
+                0
+            }
+        }
+    }
+
+    // Number of bullets in play of one type
+    pub fn live_count_of(&self, kind: &str) -> usize {
+        self.bullets.get(kind).map(|x| x.alive.len()).unwrap_or(0)
+    }
+
+    #[export]
+    pub fn live_bullets_of_type(&self, _owner: &Node2D, kind: String) -> u32 {
+        self.live_count_of(&kind) as u32
+    }
+
+    // Every live bullet, or only those of one faction, in the order they are ticked
+    pub fn query(&self, faction: Option<Faction>) -> impl Iterator<Item = BulletView> + '_ {
+        bullet_types()
+            .into_iter()
+            .filter(move |kind| faction.map_or(true, |faction| Faction::of(kind) == faction))
+            .filter_map(move |kind| self.bullets.get(kind).map(|entry| (kind, entry)))
+            .flat_map(|(kind, entry)| {
+                entry
+                    .alive
+                    .iter()
+                    .map(move |bullet| BulletView::of(kind, entry, bullet))
+            })
+    }
+
+    // Live bullets with their position inside the rectangle
+    pub fn query_rect(
+        &self,
+        rect: Rect2,
+        faction: Option<Faction>,
+    ) -> impl Iterator<Item = BulletView> + '_ {
+        self.query(faction).filter(move |x| x.in_rect(rect))
+    }
+
+    // Live bullets touching the circle, including their own radius
+    pub fn query_circle(
+        &self,
+        center: Vector2,
+        radius: f32,
+        faction: Option<Faction>,
+    ) -> impl Iterator<Item = BulletView> + '_ {
+        self.query(faction)
+            .filter(move |x| x.touches_circle(center, radius))
+    }
+
+    // Closest live bullet to a point, the first one ticked on ties
+    pub fn nearest(&self, point: Vector2, faction: Option<Faction>) -> Option<BulletView> {
+        BulletView::nearest(self.query(faction), point)
+    }
+
+    // Parses the faction of exported queries, "all" matches every faction.
+    // Unknown names are reported and give None
+    fn query_faction(name: &str) -> Option<Option<Faction>> {
+        if name == "all" {
+            return Some(None);
+        }
+        match Faction::from_name(name) {
+            Some(faction) => Some(Some(faction)),
+            None => {
+                godot_warn!("Unknown bullet faction {name}");
+                None
+            }
+        }
+    }
+
+    // Ids of the live bullets of a faction ("player", "enemy", "item" or "all")
+    // with their position in the rectangle
+    #[export]
+    pub fn bullets_in_rect(
+        &self,
+        _owner: &Node2D,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        faction: String,
+    ) -> VariantArray {
+        let ids = VariantArray::new();
+        if let Some(faction) = BulletManager::query_faction(&faction) {
+            let rect = Rect2 {
+                position: Vector2::new(x, y),
+                size: Vector2::new(width, height),
+            };
+            for bullet in self.query_rect(rect, faction) {
+                ids.push(bullet.handle.to_id());
+            }
+        }
+        ids.into_shared()
+    }
+
+    // Ids of the live bullets of a faction touching the circle
+    #[export]
+    pub fn bullets_in_circle(
+        &self,
         _owner: &Node2D,
+        x: f32,
+        y: f32,
+        radius: f32,
+        faction: String,
+    ) -> VariantArray {
+        let ids = VariantArray::new();
+        if let Some(faction) = BulletManager::query_faction(&faction) {
+            for bullet in self.query_circle(Vector2::new(x, y), radius, faction) {
+                ids.push(bullet.handle.to_id());
+            }
+        }
+        ids.into_shared()
+    }
+
+    // Id of the closest live bullet of a faction, or -1 when there are none
+    #[export]
+    pub fn nearest_bullet(&self, _owner: &Node2D, x: f32, y: f32, faction: String) -> i64 {
+        BulletManager::query_faction(&faction)
+            .and_then(|faction| self.nearest(Vector2::new(x, y), faction))
+            .map(|x| x.handle.to_id())
+            .unwrap_or(-1)
+    }
+
+    // Removes every enemy bullet from play with the despawn animation,
+    // optionally leaving a score item in the place of each.
+    // Lasers are removed as well, without leaving items.
+    // Returns the amount of bullets cancelled
+    #[export]
+    pub fn cancel_enemy_bullets(&mut self, owner: &Node2D, to_items: bool) -> u32 {
+        self.clear_lasers();
+        let mut positions = vec![];
+        for bullet_type in bullet_types() {
+            if Faction::of(bullet_type) != Faction::Enemy {
+                continue;
+            }
+            if let Some(bullet_info) = self.bullets.get_mut(bullet_type) {
+                let cancelled: Vec<Bullet> = bullet_info.alive.drain(..).collect();
+                for bullet in cancelled {
+                    positions.push(bullet.motion.position);
+                    bullet_info.despawn(bullet, self.despawn_frames);
+                }
+            }
+        }
+
+        if to_items {
+            // Only spawn as many items as are available in the pool
+            let available = self
+                .bullets
+                .get("item_score")
+                .map(|x| x.dead.len())
+                .unwrap_or(0);
+            for pos in positions.iter().take(available) {
+                self.spawn(owner, "item_score", *pos, Vector2::new(0.0, 0.0));
+            }
+        }
+
+        positions.len() as u32
+    }
+
+    // Every bullet in play, live ones first in the order they are ticked, and every laser
+    pub fn snapshot(&self) -> BulletManagerSnapshot {
+        let alive: Vec<(&'static str, &Bullet)> = bullet_types()
+            .into_iter()
+            .filter_map(|bullet_type| self.bullets.get(bullet_type).map(|x| (bullet_type, x)))
+            .flat_map(|(bullet_type, x)| x.alive.iter().map(move |bullet| (bullet_type, bullet)))
+            .collect();
+        // Index in the snapshot of every live bullet, see orbit_snapshot
+        let indexes: HashMap<BulletHandle, usize> = alive
+            .iter()
+            .enumerate()
+            .map(|(i, (_, bullet))| (bullet.handle(), i))
+            .collect();
+
+        let despawning = bullet_types()
+            .into_iter()
+            .filter_map(|bullet_type| self.bullets.get(bullet_type).map(|x| (bullet_type, x)))
+            .flat_map(|(bullet_type, x)| {
+                x.despawning
+                    .iter()
+                    .map(move |(bullet, frames)| bullet.snapshot(bullet_type, None, Some(*frames)))
+            });
+        let bullets = alive
+            .iter()
+            .map(|(bullet_type, bullet)| {
+                let orbit = bullet
+                    .motion
+                    .orbit
+                    .and_then(|orbit| orbit_snapshot(orbit, &indexes));
+                bullet.snapshot(bullet_type, orbit, None)
+            })
+            .chain(despawning)
+            .collect();
+
+        let lasers = self
+            .lasers
+            .iter()
+            .map(|laser| LaserSnapshot {
+                origin: save_state::to_position(laser.config.origin),
+                angle: laser.config.angle,
+                length: laser.config.length,
+                width: laser.config.width,
+                warning_ms: laser.config.warning_ms,
+                active_ms: laser.config.active_ms,
+                grow_ms: laser.config.grow_ms,
+                rotation_speed: laser.config.rotation_speed,
+                wave_amplitude: laser.config.wave_amplitude,
+                wave_length: laser.config.wave_length,
+                wave_speed: laser.config.wave_speed,
+                age: laser.age,
+            })
+            .collect();
+
+        BulletManagerSnapshot {
+            bullets,
+            lasers,
+            next_group: self.next_group,
+        }
+    }
+
+    // Removes every bullet and laser from play without any animation
+    pub fn clear(&mut self) {
+        self.clear_lasers();
+        for bullet_info in self.bullets.values_mut() {
+            let despawning: Vec<Bullet> = bullet_info
+                .despawning
+                .drain(..)
+                .map(|(bullet, _)| bullet)
+                .collect();
+            let alive: Vec<Bullet> = bullet_info.alive.drain(..).collect();
+            for bullet in alive.into_iter().chain(despawning) {
+                let node = unsafe { bullet.node.assume_safe() };
+                node.set_visible(false);
+                bullet_info.kill(bullet);
+            }
+        }
+    }
+
+    // Replaces every bullet in play (including despawning ones) and laser with the snapshot.
+    // Handles to bullets from before the restore are invalidated
+    pub fn restore(&mut self, owner: &Node2D, snapshot: &BulletManagerSnapshot) {
+        self.clear();
+        self.next_group = snapshot.next_group;
+
+        // Handle of each restored bullet, by index in the snapshot
+        let mut handles = vec![];
+        for bullet in &snapshot.bullets {
+            if !self.bullets.contains_key(&bullet.kind) {
+                godot_warn!("Skipping saved bullet of unknown type {}", bullet.kind);
+                handles.push(None);
+                continue;
+            }
+            // Pools smaller than when saved drop the extra bullets
+            let pos = save_state::from_position(bullet.position);
+            let velocity = Vector2::new(bullet.dx, bullet.dy);
+            let (r, g, b, a) = bullet.tint;
+            let visual = BulletVisual {
+                tint: Color::from_rgba(r, g, b, a),
+                scale: bullet.scale,
+                frame: bullet.frame,
+            };
+            let handle = self.spawn_styled(owner, &bullet.kind, pos, velocity, visual);
+            if let Some(spawned) = handle.and_then(|x| self.bullet_mut(x)) {
+                spawned.grazed = bullet.grazed;
+                spawned.motion.age = bullet.age;
+                spawned.motion.homing = bullet.homing;
+                spawned.group = bullet.group;
+                spawned.motion.burst = bullet.burst.as_ref().map(|burst| Burst {
+                    frames: burst.frames,
+                    kind: burst.kind.clone(),
+                    count: burst.count,
+                    speed: burst.speed,
+                    angle: burst.angle,
+                });
+            }
+            // Despawning bullets pick their animation back up, nothing refers to them
+            if let (Some(frames), Some(_)) = (bullet.despawn_frames, handle) {
+                let progress = despawn_progress(frames, self.despawn_frames);
+                let bullet_info = self.bullets.get_mut(&bullet.kind).unwrap();
+                let last = bullet_info.alive.len() - 1;
+                let despawning = bullet_info.swap_remove_alive(last);
+                despawning.show(progress);
+                bullet_info.despawn(despawning, frames);
+                handles.push(None);
+                continue;
+            }
+            handles.push(handle);
+        }
+
+        // Parents may come after their children, so orbits are set once all are spawned
+        for (bullet, handle) in snapshot.bullets.iter().zip(&handles) {
+            let (orbit, handle) = match (&bullet.orbit, handle) {
+                (Some(orbit), Some(handle)) => (orbit, *handle),
+                _ => continue,
+            };
+            if let Some(orbit) = restored_orbit(orbit, &handles) {
+                self.bullet_mut(handle).unwrap().motion.orbit = Some(orbit);
+            }
+        }
+
+        for laser in &snapshot.lasers {
+            let count = self.lasers.len();
+            self.spawn_laser(
+                owner,
+                LaserConfig {
+                    origin: save_state::from_position(laser.origin),
+                    angle: laser.angle,
+                    length: laser.length,
+                    width: laser.width,
+                    warning_ms: laser.warning_ms,
+                    active_ms: laser.active_ms,
+                    grow_ms: laser.grow_ms,
+                    rotation_speed: laser.rotation_speed,
+                    wave_amplitude: laser.wave_amplitude,
+                    wave_length: laser.wave_length,
+                    wave_speed: laser.wave_speed,
+                },
+            );
+            if self.lasers.len() > count {
+                self.lasers.last_mut().unwrap().age = laser.age;
+            }
+        }
+    }
+
+    // Called by Player and GenericEnemy to spawn a bullet.
+    // Returns the id of a handle to the bullet for the methods below,
+    // or -1 when the pool is exhausted
+    #[export]
+    pub fn spawn_bullet(
+        &mut self,
+        owner: &Node2D,
         kind: String,
         x: f32,
         y: f32,
         dx: f32,
         dy: f32,
-    ) {
-        let bullets = self.bullets.get_mut(&kind).unwrap();
+    ) -> i64 {
+        match self.spawn(owner, &kind, Vector2::new(x, y), Vector2::new(dx, dy)) {
+            Some(handle) => handle.to_id(),
+            None => -1,
+        }
+    }
+
+    pub fn spawn(
+        &mut self,
+        owner: &Node2D,
+        kind: &str,
+        pos: Vector2,
+        velocity: Vector2,
+    ) -> Option<BulletHandle> {
+        self.spawn_styled(owner, kind, pos, velocity, BulletVisual::default())
+    }
+
+    pub fn spawn_styled(
+        &mut self,
+        owner: &Node2D,
+        kind: &str,
+        pos: Vector2,
+        velocity: Vector2,
+        visual: BulletVisual,
+    ) -> Option<BulletHandle> {
+        let bullets = match self.bullets.get_mut(kind) {
+            Some(bullets) => bullets,
+            None => {
+                godot_warn!("Unknown bullet type {kind}");
+                return None;
+            }
+        };
         // Fetch a bullet from the dead list and provide parameters
-        let mut bullet = bullets.dead.pop().unwrap();
-        bullet.dx = dx;
-        bullet.dy = dy;
+        let mut bullet = match bullets.dead.pop() {
+            Some(bullet) => bullet,
+            None => {
+                // Drop the spawn, the pool size needs raising in bullet_amounts
+                signals::emit_deferred(owner, "pool_exhausted", &[kind.to_variant()]);
+                return None;
+            }
+        };
+        bullet.motion = Motion::new(pos, velocity);
+        bullet.grazed = false;
 
         let node = unsafe { bullet.node.assume_safe() };
-        node.set_global_position(Vector2::new(x, y));
+        node.set_global_position(pos);
+        let rotation = facing(bullets.rotate, velocity).unwrap_or(0.0);
+        node.set_rotation(rotation as f64);
         node.set_visible(true);
+        bullet.set_visual(visual);
 
         // Place the bullet into the living list to be ticked
-        bullets.alive.push(bullet);
+        let handle = bullet.handle();
+        bullets.push_alive(bullet);
+        Some(handle)
+    }
+
+    // The live bullet a handle refers to, None once it has been removed from play
+    fn bullet_mut(&mut self, handle: BulletHandle) -> Option<&mut Bullet> {
+        let bullet_type = self.slot_types.get(handle.slot as usize)?;
+        let entry = self.bullets.get_mut(*bullet_type)?;
+        let i = entry.alive_position(handle)?;
+        Some(&mut entry.alive[i])
+    }
+
+    fn find(&self, handle: BulletHandle) -> Option<(&'static str, &BulletEntry, &Bullet)> {
+        let bullet_type = *self.slot_types.get(handle.slot as usize)?;
+        let entry = self.bullets.get(bullet_type)?;
+        let i = entry.alive_position(handle)?;
+        Some((bullet_type, entry, &entry.alive[i]))
+    }
+
+    // Current state of the live bullet a handle refers to
+    pub fn get(&self, handle: BulletHandle) -> Option<BulletView> {
+        self.find(handle)
+            .map(|(kind, entry, bullet)| BulletView::of(kind, entry, bullet))
+    }
+
+    fn bullet_position(&self, handle: BulletHandle) -> Option<Vector2> {
+        self.get(handle).map(|x| x.position)
+    }
+
+    // Changes the velocity of a live bullet, releasing it from any orbit
+    pub fn set_velocity(&mut self, handle: BulletHandle, velocity: Vector2) -> bool {
+        match self.bullet_mut(handle) {
+            Some(bullet) => {
+                bullet.motion.dx = velocity.x;
+                bullet.motion.dy = velocity.y;
+                bullet.motion.orbit = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Moves a live bullet, releasing it from any orbit
+    pub fn set_position(&mut self, handle: BulletHandle, pos: Vector2) -> bool {
+        match self.bullet_mut(handle) {
+            Some(bullet) => {
+                unsafe { bullet.node.assume_safe() }.set_global_position(pos);
+                bullet.motion.position = pos;
+                bullet.motion.orbit = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Removes a live bullet from play, optionally playing the despawn animation
+    pub fn remove(&mut self, handle: BulletHandle, despawn: bool) -> bool {
+        let bullet_type = match self.slot_types.get(handle.slot as usize) {
+            Some(bullet_type) => *bullet_type,
+            None => return false,
+        };
+        let bullet_info = match self.bullets.get_mut(bullet_type) {
+            Some(bullet_info) => bullet_info,
+            None => return false,
+        };
+        let i = match bullet_info.alive_position(handle) {
+            Some(i) => i,
+            None => return false,
+        };
+        let bullet = bullet_info.swap_remove_alive(i);
+        if despawn {
+            bullet_info.despawn(bullet, self.despawn_frames);
+        } else {
+            unsafe { bullet.node.assume_safe() }.set_visible(false);
+            bullet_info.kill(bullet);
+        }
+        true
+    }
+
+    // The methods below take bullet ids as returned by spawn_bullet,
+    // and return false when the bullet is no longer in play
+
+    #[export]
+    pub fn is_bullet_alive(&self, _owner: &Node2D, id: i64) -> bool {
+        BulletHandle::from_id(id)
+            .and_then(|x| self.find(x))
+            .is_some()
+    }
+
+    // Position of the bullet, or null once removed
+    #[export]
+    pub fn get_bullet_position(&self, _owner: &Node2D, id: i64) -> Option<Vector2> {
+        BulletHandle::from_id(id).and_then(|x| self.bullet_position(x))
+    }
+
+    // Velocity of the bullet (px/sec), or null once removed
+    #[export]
+    pub fn get_bullet_velocity(&self, _owner: &Node2D, id: i64) -> Option<Vector2> {
+        BulletHandle::from_id(id)
+            .and_then(|x| self.get(x))
+            .map(|x| x.velocity)
+    }
+
+    #[export]
+    pub fn set_bullet_velocity(&mut self, _owner: &Node2D, id: i64, dx: f32, dy: f32) -> bool {
+        match BulletHandle::from_id(id) {
+            Some(handle) => self.set_velocity(handle, Vector2::new(dx, dy)),
+            None => false,
+        }
+    }
+
+    #[export]
+    pub fn set_bullet_position(&mut self, _owner: &Node2D, id: i64, x: f32, y: f32) -> bool {
+        match BulletHandle::from_id(id) {
+            Some(handle) => self.set_position(handle, Vector2::new(x, y)),
+            None => false,
+        }
+    }
+
+    // Makes a live bullet turn towards the nearest target, or stop doing so with None.
+    // Player shots home on enemies and enemy bullets on the player, items can't home
+    pub fn set_homing(&mut self, handle: BulletHandle, turn_rate: Option<f32>) -> bool {
+        let kind = self.slot_types.get(handle.slot as usize);
+        if matches!(kind, Some(kind) if Faction::of(kind) == Faction::Item) {
+            return false;
+        }
+        match self.bullet_mut(handle) {
+            Some(bullet) => {
+                bullet.motion.homing = turn_rate;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Turns the bullet towards the nearest target by up to `turn_rate` radians/sec,
+    // 0 stops it homing. Fails for items, see set_homing
+    #[export]
+    pub fn set_bullet_homing(&mut self, _owner: &Node2D, id: i64, turn_rate: f32) -> bool {
+        let turn_rate = if turn_rate > 0.0 { Some(turn_rate) } else { None };
+        match BulletHandle::from_id(id) {
+            Some(handle) => self.set_homing(handle, turn_rate),
+            None => false,
+        }
+    }
+
+    // Changes the look of a live bullet, see BulletVisual
+    pub fn set_visual(&mut self, handle: BulletHandle, visual: BulletVisual) -> bool {
+        match self.bullet_mut(handle) {
+            Some(bullet) => {
+                bullet.set_visual(visual);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Tints, scales and picks the sprite frame of the bullet,
+    // a frame of -1 plays the bullet's animation
+    #[export]
+    pub fn set_bullet_visual(
+        &mut self,
+        _owner: &Node2D,
+        id: i64,
+        tint: Color,
+        scale: f32,
+        frame: i64,
+    ) -> bool {
+        match BulletHandle::from_id(id) {
+            Some(handle) => self.set_visual(handle, BulletVisual { tint, scale, frame }),
+            None => false,
+        }
+    }
+
+    // Removes the bullet, with the despawn animation when `despawn` is set
+    #[export]
+    pub fn kill_bullet(&mut self, _owner: &Node2D, id: i64, despawn: bool) -> bool {
+        match BulletHandle::from_id(id) {
+            Some(handle) => self.remove(handle, despawn),
+            None => false,
+        }
+    }
+
+    // Replaces the bullet with a ring of `count` bullets of type `kind` after `frames` frames.
+    // Frames rather than simulation time, so the delay depends on the framerate
+    // and ignores slow-motion. Pauses and hit-stops still hold it
+    #[export]
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_bullet_burst(
+        &mut self,
+        _owner: &Node2D,
+        id: i64,
+        frames: u32,
+        kind: String,
+        count: u32,
+        speed: f32,
+        angle: f32,
+    ) -> bool {
+        match BulletHandle::from_id(id).and_then(|x| self.bullet_mut(x)) {
+            Some(bullet) => {
+                bullet.motion.burst = Some(Burst {
+                    frames: frames.max(1),
+                    kind,
+                    count,
+                    speed,
+                    angle,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Makes the bullet circle the parent bullet, keeping its current distance from it
+    #[export]
+    pub fn set_bullet_orbit(
+        &mut self,
+        _owner: &Node2D,
+        id: i64,
+        parent_id: i64,
+        angular_speed: f32,
+    ) -> bool {
+        let (handle, parent) = match (BulletHandle::from_id(id), BulletHandle::from_id(parent_id)) {
+            (Some(handle), Some(parent)) if handle != parent => (handle, parent),
+            _ => return false,
+        };
+        let (pos, parent_pos) = match (self.bullet_position(handle), self.bullet_position(parent)) {
+            (Some(pos), Some(parent_pos)) => (pos, parent_pos),
+            _ => return false,
+        };
+        let offset = pos - parent_pos;
+        self.bullet_mut(handle).unwrap().motion.orbit = Some(Orbit {
+            parent,
+            radius: offset.length(),
+            angle: offset.y.atan2(offset.x),
+            angular_speed,
+        });
+        true
+    }
+
+    // Creates an empty group of bullets to be commanded together
+    #[export]
+    pub fn create_group(&mut self, _owner: &Node2D) -> i64 {
+        self.next_group += 1;
+        self.next_group as i64
+    }
+
+    // Moves the bullet into a group, or out of any with -1.
+    // Rings from bursting bullets join the group of the bullet
+    #[export]
+    pub fn set_bullet_group(&mut self, _owner: &Node2D, id: i64, group: i64) -> bool {
+        match BulletHandle::from_id(id).and_then(|x| self.bullet_mut(x)) {
+            Some(bullet) => {
+                bullet.group = u32::try_from(group).ok();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Live bullets of a group, none for ids create_group never returns
+    fn group_mut(&mut self, group: i64) -> impl Iterator<Item = &mut Bullet> {
+        self.bullets
+            .values_mut()
+            .flat_map(|x| x.alive.iter_mut())
+            .filter(move |x| in_group(x.group, group))
+    }
+
+    // Stops every bullet of the group, releasing orbiting ones.
+    // Returns the amount of bullets commanded
+    #[export]
+    pub fn group_stop(&mut self, _owner: &Node2D, group: i64) -> u32 {
+        let mut count = 0;
+        for bullet in self.group_mut(group) {
+            bullet.motion.dx = 0.0;
+            bullet.motion.dy = 0.0;
+            bullet.motion.orbit = None;
+            count += 1;
+        }
+        count
+    }
+
+    // Sends every bullet of the group towards the current position of the player
+    // nearest to it, releasing orbiting ones. Returns the amount of bullets commanded
+    #[export]
+    pub fn group_aim_at_player(&mut self, _owner: &Node2D, group: i64, speed: f32) -> u32 {
+        let (_, hitboxes) = player::live_players(&self.players);
+        let players: Vec<Vector2> = hitboxes.iter().map(|x| x.position).collect();
+        let mut count = 0;
+        for bullet in self.group_mut(group) {
+            let pos = bullet.motion.position;
+            let player_pos = match bullet_motion::nearest(players.iter().copied(), pos) {
+                Some(player_pos) => player_pos,
+                None => return 0,
+            };
+            // Bullets already on the player keep their velocity
+            if pos != player_pos {
+                let direction = (player_pos - pos).normalized();
+                bullet.motion.dx = direction.x * speed;
+                bullet.motion.dy = direction.y * speed;
+            }
+            bullet.motion.orbit = None;
+            count += 1;
+        }
+        count
     }
 
     // Creates a new instance of a "scene"
@@ -257,3 +1693,208 @@ impl BulletManager {
         instance.try_cast::<Root>().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bullet_handle::handle;
+
+    fn view(slot: u32, x: f32, y: f32, radius: f32) -> BulletView {
+        BulletView {
+            handle: handle(slot, 0),
+            kind: "orb_bullet",
+            position: Vector2::new(x, y),
+            velocity: Vector2::new(0.0, 0.0),
+            radius,
+            age: 0.0,
+        }
+    }
+
+    #[test]
+    fn rect_includes_its_edges() {
+        let rect = Rect2 {
+            position: Vector2::new(10.0, 20.0),
+            size: Vector2::new(100.0, 50.0),
+        };
+        assert!(view(0, 50.0, 40.0, 4.0).in_rect(rect));
+        assert!(view(0, 10.0, 20.0, 4.0).in_rect(rect));
+        assert!(view(0, 110.0, 70.0, 4.0).in_rect(rect));
+        // Only the position counts, not the radius
+        assert!(!view(0, 8.0, 40.0, 4.0).in_rect(rect));
+        assert!(!view(0, 50.0, 70.5, 4.0).in_rect(rect));
+    }
+
+    #[test]
+    fn circle_includes_the_bullet_radius() {
+        let center = Vector2::new(0.0, 0.0);
+        assert!(view(0, 3.0, 4.0, 0.0).touches_circle(center, 5.0));
+        assert!(view(0, 12.0, 0.0, 4.0).touches_circle(center, 8.0));
+        assert!(!view(0, 12.5, 0.0, 4.0).touches_circle(center, 8.0));
+    }
+
+    #[test]
+    fn nearest_prefers_the_first_on_ties() {
+        let point = Vector2::new(0.0, 0.0);
+        let bullets = [
+            view(0, 30.0, 0.0, 4.0),
+            view(1, 0.0, -10.0, 4.0),
+            view(2, 10.0, 0.0, 4.0),
+        ];
+        let nearest = BulletView::nearest(bullets, point).unwrap();
+        assert_eq!(nearest.handle.slot, 1);
+        assert_eq!(BulletView::nearest([], point), None);
+    }
+
+    #[test]
+    fn despawning_lasts_its_frames() {
+        // Despawned with 2 frames left, next to one about to finish
+        let mut despawning = vec![("expired", 2), ("cancelled", 0)];
+        assert_eq!(tick_despawning(&mut despawning), vec!["cancelled"]);
+        assert_eq!(despawning, vec![("expired", 1)]);
+        assert_eq!(tick_despawning(&mut despawning), vec![]);
+        assert_eq!(tick_despawning(&mut despawning), vec!["expired"]);
+        assert!(despawning.is_empty());
+    }
+
+    #[test]
+    fn despawn_progress_counts_down() {
+        assert_eq!(despawn_progress(8, 8), 1.0);
+        assert_eq!(despawn_progress(4, 8), 0.5);
+        assert_eq!(despawn_progress(0, 8), 0.0);
+        // No animation at all
+        assert_eq!(despawn_progress(0, 0), 0.0);
+    }
+
+    fn burst(count: u32) -> Burst {
+        Burst {
+            frames: 1,
+            kind: "orb_bullet".to_string(),
+            count,
+            speed: 10.0,
+            angle: 0.0,
+        }
+    }
+
+    #[test]
+    fn rings_are_evenly_spaced() {
+        let bullets = ring(&burst(4), None, BulletVisual::default());
+        let expected = [(10.0, 0.0), (0.0, 10.0), (-10.0, 0.0), (0.0, -10.0)];
+        assert_eq!(bullets.len(), expected.len());
+        for (bullet, (dx, dy)) in bullets.iter().zip(expected) {
+            assert!((bullet.velocity.x - dx).abs() < 0.001);
+            assert!((bullet.velocity.y - dy).abs() < 0.001);
+        }
+        // Always at least one bullet
+        assert_eq!(ring(&burst(0), None, BulletVisual::default()).len(), 1);
+    }
+
+    #[test]
+    fn rings_keep_the_look_of_their_bullet() {
+        let visual = BulletVisual {
+            tint: Color::from_rgba(1.0, 0.2, 0.2, 0.8),
+            scale: 1.5,
+            frame: 2,
+        };
+        let bullets = ring(&burst(6), None, visual);
+        assert!(bullets.iter().all(|x| x.visual == visual));
+    }
+
+    #[test]
+    fn rings_join_the_group_of_their_bullet() {
+        let bullets = ring(&burst(3), Some(2), BulletVisual::default());
+        assert!(bullets.iter().all(|x| x.group == Some(2)));
+        let bullets = ring(&burst(3), None, BulletVisual::default());
+        assert!(bullets.iter().all(|x| x.group.is_none()));
+    }
+
+    #[test]
+    fn unknown_group_ids_match_nothing() {
+        assert!(in_group(Some(1), 1));
+        assert!(!in_group(Some(1), 2));
+        // Ungrouped bullets aren't a group of their own
+        assert!(!in_group(None, -1));
+        assert!(!in_group(Some(1), -1));
+        // Ids out of range don't wrap around onto other groups
+        assert!(!in_group(Some(1), (1 << 32) + 1));
+    }
+
+    #[test]
+    fn orbit_parents_survive_snapshot_and_restore() {
+        let parent = handle(5, 2);
+        let orbit = Orbit {
+            parent,
+            radius: 20.0,
+            angle: 1.0,
+            angular_speed: 3.0,
+        };
+        // The child is stored before its parent
+        let child = handle(7, 0);
+        let indexes = HashMap::from([(child, 0), (parent, 1)]);
+        let snapshot = orbit_snapshot(orbit, &indexes).unwrap();
+        assert_eq!(snapshot.parent, 1);
+
+        // Both are spawned again under new handles
+        let restored_parent = handle(40, 6);
+        let restored_child = handle(41, 6);
+        let handles = [Some(restored_child), Some(restored_parent)];
+        let restored = restored_orbit(&snapshot, &handles).unwrap();
+        assert_eq!(
+            restored,
+            Orbit {
+                parent: restored_parent,
+                ..orbit
+            }
+        );
+
+        // Parents removed before saving or dropped while restoring
+        assert!(orbit_snapshot(orbit, &HashMap::from([(child, 0)])).is_none());
+        assert!(restored_orbit(&snapshot, &[Some(restored_child), None]).is_none());
+        assert!(restored_orbit(&snapshot, &[Some(restored_child)]).is_none());
+    }
+
+    #[test]
+    fn bullets_face_their_velocity() {
+        let right = facing(true, Vector2::new(10.0, 0.0)).unwrap();
+        assert!(right.abs() < 0.001);
+        let down = facing(true, Vector2::new(0.0, 10.0)).unwrap();
+        assert!((down - PI / 2.0).abs() < 0.001);
+        let up_left = facing(true, Vector2::new(-10.0, -10.0)).unwrap();
+        assert!((up_left + 3.0 * PI / 4.0).abs() < 0.001);
+        // Standing still keeps the last direction, and some types never turn
+        assert_eq!(facing(true, Vector2::new(0.0, 0.0)), None);
+        assert_eq!(facing(false, Vector2::new(10.0, 0.0)), None);
+    }
+
+    #[test]
+    fn despawning_fades_from_the_bullet_look() {
+        let visual = BulletVisual {
+            tint: Color::from_rgba(0.2, 0.4, 1.0, 0.8),
+            scale: 2.0,
+            frame: 3,
+        };
+        assert_eq!(visual.faded(1.0), visual);
+        let half = visual.faded(0.5);
+        assert_eq!(half.scale, 1.0);
+        assert_eq!(half.tint, Color::from_rgba(0.2, 0.4, 1.0, 0.4));
+        assert_eq!(half.frame, 3);
+        let gone = visual.faded(0.0);
+        assert_eq!(gone.scale, 0.0);
+        assert_eq!(gone.tint.a, 0.0);
+    }
+
+    #[test]
+    fn negative_frames_keep_animating() {
+        assert_eq!(BulletVisual::default().held_frame(), None);
+        let held = BulletVisual {
+            frame: 0,
+            ..Default::default()
+        };
+        assert_eq!(held.held_frame(), Some(0));
+        let other = BulletVisual {
+            frame: -5,
+            ..Default::default()
+        };
+        assert_eq!(other.held_frame(), None);
+    }
+}
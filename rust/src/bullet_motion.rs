@@ -0,0 +1,764 @@
+use gdnative::prelude::*;
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::bullet_handle::BulletHandle;
+use crate::collision;
+use crate::enemy::movement::{PLAYFIELD_HEIGHT, PLAYFIELD_WIDTH};
+
+// Batches smaller than this are integrated on the calling thread,
+// as splitting them up costs more than it saves
+pub const PARALLEL_THRESHOLD: usize = 512;
+// Fewest bullets given to a thread at once
+const PARALLEL_CHUNK: usize = 256;
+// Deepest chain of orbiting parents followed, only reached by bullets orbiting
+// each other, whose positions are then predicted as if they moved freely
+const MAX_ORBIT_DEPTH: u32 = 16;
+
+// Replaces a bullet with a ring of bullets after a number of frames
+#[derive(Clone, PartialEq, Debug)]
+pub struct Burst {
+    // Ticks left before bursting. Counted in frames rather than simulation time,
+    // so the delay varies with the framerate and isn't stretched by slow-motion
+    pub frames: u32,
+    // Bullet type of the ring
+    pub kind: String,
+    pub count: u32,
+    pub speed: f32,
+    // Direction of the first bullet of the ring (radians)
+    pub angle: f32,
+}
+
+// Circles a parent bullet instead of moving by its velocity,
+// flying off at its current velocity once the parent is removed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Orbit {
+    pub parent: BulletHandle,
+    pub radius: f32,
+    // Current direction from the parent (radians)
+    pub angle: f32,
+    // Change in angle over time (radians/sec)
+    pub angular_speed: f32,
+}
+
+// State of a bullet changed by each tick, kept as plain data apart
+// from the bullet's nodes so bullets can be integrated on any thread
+#[derive(Clone, PartialEq, Debug)]
+pub struct Motion {
+    pub position: Vector2,
+    pub dx: f32,
+    pub dy: f32,
+    // Simulation time since the bullet was spawned (secs)
+    pub age: f32,
+    pub burst: Option<Burst>,
+    pub orbit: Option<Orbit>,
+    // Rate the bullet turns towards the nearest target (radians/sec)
+    pub homing: Option<f32>,
+}
+
+impl Motion {
+    pub fn new(position: Vector2, velocity: Vector2) -> Self {
+        Self {
+            position,
+            dx: velocity.x,
+            dy: velocity.y,
+            age: 0.0,
+            burst: None,
+            orbit: None,
+            homing: None,
+        }
+    }
+}
+
+// A player's position and hitbox radius
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PlayerHitbox {
+    pub position: Vector2,
+    pub radius: f32,
+}
+
+// Settings shared by every bullet of a type for one tick
+pub struct StepContext<'a> {
+    pub deltatime: f32,
+    pub players: &'a [PlayerHitbox],
+    // Speed items are pulled towards the nearest player, None for other bullets
+    pub homing_speed: Option<f32>,
+    pub margin: f32,
+    pub lifetime_ms: i64,
+    // Where orbited bullets will be after this tick
+    pub parents: &'a HashMap<BulletHandle, Vector2>,
+    // Positions homing bullets turn towards, the enemies for player shots
+    // and the players for enemy bullets
+    pub targets: &'a [Vector2],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fate {
+    // Still in play, to be checked for collisions
+    Moving,
+    // Went past the edge of the screen and its margin
+    OffScreen,
+    // To be replaced by its ring
+    Burst,
+    // Outlived its lifetime, plays the despawn animation
+    Expired,
+}
+
+impl Fate {
+    // Wether the bullet can still hit something this tick. Bullets leaving play
+    // don't, including expired ones despawning on top of a player
+    pub fn collides(&self) -> bool {
+        *self == Fate::Moving
+    }
+}
+
+// Result of moving a bullet for one tick
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Step {
+    pub previous: Vector2,
+    pub fate: Fate,
+    // Player whose hitbox the bullet came closest to along the path
+    // travelled this tick, None when there are no players.
+    // Others may be in reach too, see `players_in_reach`
+    pub player: Option<usize>,
+    // Distance from that path to the edge of the player's hitbox, negative within it
+    pub player_gap: f32,
+}
+
+// Moves a bullet for one tick, only reading and writing its own state
+pub fn integrate(motion: &mut Motion, context: &StepContext) -> Step {
+    let deltatime = context.deltatime;
+    let previous = motion.position;
+    if let Some(speed) = context.homing_speed {
+        let players = context.players.iter().map(|x| x.position);
+        // Items already on the player keep their velocity
+        if let Some(target) = nearest(players, previous).filter(|x| *x != previous) {
+            let direction = (target - previous).normalized();
+            motion.dx = direction.x * speed;
+            motion.dy = direction.y * speed;
+        }
+    }
+    if let Some(turn_rate) = motion.homing {
+        steer(motion, previous, turn_rate * deltatime, context.targets);
+    }
+
+    let mut next = previous + Vector2::new(motion.dx, motion.dy) * deltatime;
+    if let Some(orbit) = &mut motion.orbit {
+        match context.parents.get(&orbit.parent) {
+            Some(parent) => {
+                orbit.angle += orbit.angular_speed * deltatime;
+                next = *parent + Vector2::new(orbit.angle.cos(), orbit.angle.sin()) * orbit.radius;
+                // Kept up to date so the bullet flies off smoothly once released
+                let velocity = (next - previous) / deltatime;
+                motion.dx = velocity.x;
+                motion.dy = velocity.y;
+            }
+            None => motion.orbit = None,
+        }
+    }
+    motion.position = next;
+    motion.age += deltatime;
+    let bursting = match &mut motion.burst {
+        Some(burst) => {
+            burst.frames = burst.frames.saturating_sub(1);
+            burst.frames == 0
+        }
+        None => false,
+    };
+
+    let margin = context.margin;
+    let expired =
+        context.lifetime_ms >= 0 && motion.age * 1000.0 >= context.lifetime_ms as f32;
+    let fate = if next.x < -margin
+        || next.y < -margin
+        || next.x > PLAYFIELD_WIDTH + margin
+        || next.y > PLAYFIELD_HEIGHT + margin
+    {
+        Fate::OffScreen
+    } else if bursting {
+        Fate::Burst
+    } else if expired {
+        Fate::Expired
+    } else {
+        Fate::Moving
+    };
+
+    // The first player wins ties
+    let mut player = None;
+    let mut player_gap = f32::INFINITY;
+    for (i, gap) in player_gaps(context.players, previous, next) {
+        if gap < player_gap {
+            player = Some(i);
+            player_gap = gap;
+        }
+    }
+
+    Step {
+        previous,
+        fate,
+        player,
+        player_gap,
+    }
+}
+
+// Where the parents of orbiting bullets will be after this tick, given every live
+// bullet by handle. Parents that orbit a bullet themselves are placed around where
+// their own parent will be, the same way `integrate` moves them, so nested orbits
+// stay in formation
+pub fn orbit_parents(
+    bullets: &HashMap<BulletHandle, &Motion>,
+    deltatime: f32,
+) -> HashMap<BulletHandle, Vector2> {
+    bullets
+        .values()
+        .filter_map(|x| x.orbit.map(|orbit| orbit.parent))
+        .filter_map(|parent| Some((parent, predict(parent, bullets, deltatime, 0)?)))
+        .collect()
+}
+
+// Position of a bullet after this tick, None once it has been removed.
+// Only depends on the bullets, not on the order they are predicted in
+fn predict(
+    handle: BulletHandle,
+    bullets: &HashMap<BulletHandle, &Motion>,
+    deltatime: f32,
+    depth: u32,
+) -> Option<Vector2> {
+    let motion = bullets.get(&handle)?;
+    let orbit = match motion.orbit {
+        Some(orbit) if depth < MAX_ORBIT_DEPTH => {
+            predict(orbit.parent, bullets, deltatime, depth + 1).map(|parent| (orbit, parent))
+        }
+        _ => None,
+    };
+    Some(match orbit {
+        Some((orbit, parent)) => {
+            let angle = orbit.angle + orbit.angular_speed * deltatime;
+            parent + Vector2::new(angle.cos(), angle.sin()) * orbit.radius
+        }
+        // Released from its orbit by `integrate` when the parent is gone
+        None => motion.position + Vector2::new(motion.dx, motion.dy) * deltatime,
+    })
+}
+
+// Distance from the path between two points to the edge of each player's hitbox,
+// negative within it, along with the index of the player
+pub fn player_gaps(
+    players: &[PlayerHitbox],
+    from: Vector2,
+    to: Vector2,
+) -> impl Iterator<Item = (usize, f32)> + '_ {
+    players
+        .iter()
+        .map(move |x| collision::segment_distance(x.position, from, to) - x.radius)
+        .enumerate()
+}
+
+// Players whose hitbox is within `reach` of the path between two points,
+// nearest first, the first one winning ties
+pub fn players_in_reach(
+    players: &[PlayerHitbox],
+    from: Vector2,
+    to: Vector2,
+    reach: f32,
+) -> Vec<usize> {
+    let mut touching: Vec<(usize, f32)> = player_gaps(players, from, to)
+        .filter(|(_, gap)| *gap <= reach)
+        .collect();
+    touching.sort_by(|a, b| a.1.total_cmp(&b.1));
+    touching.into_iter().map(|(i, _)| i).collect()
+}
+
+// Point closest to `to`, the first one winning ties
+pub fn nearest(points: impl IntoIterator<Item = Vector2>, to: Vector2) -> Option<Vector2> {
+    points.into_iter().fold(None, |nearest, x| match nearest {
+        Some(nearest) if nearest.distance_to(to) <= x.distance_to(to) => Some(nearest),
+        _ => Some(x),
+    })
+}
+
+// Turns the bullet's velocity towards the nearest target by at most `max_turn`
+// radians, keeping its speed
+fn steer(motion: &mut Motion, pos: Vector2, max_turn: f32, targets: &[Vector2]) {
+    let target = match nearest(targets.iter().copied(), pos) {
+        Some(target) if target != pos => target,
+        _ => return,
+    };
+    let velocity = Vector2::new(motion.dx, motion.dy);
+    let speed = velocity.length();
+    if speed == 0.0 {
+        return;
+    }
+    let current = velocity.y.atan2(velocity.x);
+    let wanted = (target.y - pos.y).atan2(target.x - pos.x);
+    // Shortest way round, in [-PI, PI)
+    let difference = (wanted - current + PI).rem_euclid(2.0 * PI) - PI;
+    let angle = current + difference.clamp(-max_turn, max_turn);
+    motion.dx = angle.cos() * speed;
+    motion.dy = angle.sin() * speed;
+}
+
+// Integrates every bullet, returning their steps in order.
+// Each bullet only depends on its own state, so the results are the same
+// whether or not the batch is split, and for any number of threads
+pub fn integrate_all<T, F>(bullets: &mut [T], motion: F, context: &StepContext) -> Vec<Step>
+where
+    T: Send,
+    F: Fn(&mut T) -> &mut Motion + Sync + Send,
+{
+    let parallel = bullets.len() >= PARALLEL_THRESHOLD;
+    integrate_batch(bullets, motion, context, parallel)
+}
+
+fn integrate_batch<T, F>(
+    bullets: &mut [T],
+    motion: F,
+    context: &StepContext,
+    parallel: bool,
+) -> Vec<Step>
+where
+    T: Send,
+    F: Fn(&mut T) -> &mut Motion + Sync + Send,
+{
+    if parallel {
+        bullets
+            .par_iter_mut()
+            .with_min_len(PARALLEL_CHUNK)
+            .map(|x| integrate(motion(x), context))
+            .collect()
+    } else {
+        bullets
+            .iter_mut()
+            .map(|x| integrate(motion(x), context))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use crate::bullet_handle::handle;
+    use crate::rng::Pcg32;
+
+    fn context<'a>(
+        deltatime: f32,
+        players: &'a [PlayerHitbox],
+        parents: &'a HashMap<BulletHandle, Vector2>,
+    ) -> StepContext<'a> {
+        StepContext {
+            deltatime,
+            players,
+            homing_speed: None,
+            margin: 16.0,
+            lifetime_ms: -1,
+            parents,
+            targets: &[],
+        }
+    }
+
+    #[test]
+    fn expires_at_its_lifetime() {
+        let parents = HashMap::new();
+        let context = StepContext {
+            lifetime_ms: 500,
+            ..context(0.25, &[], &parents)
+        };
+        let mut motion = Motion::new(Vector2::new(100.0, 100.0), Vector2::new(0.0, 0.0));
+        assert_eq!(integrate(&mut motion, &context).fate, Fate::Moving);
+        // Exactly 500ms old
+        assert_eq!(integrate(&mut motion, &context).fate, Fate::Expired);
+    }
+
+    #[test]
+    fn expired_bullets_dont_collide() {
+        let players = [PlayerHitbox {
+            position: Vector2::new(100.0, 100.0),
+            radius: 4.0,
+        }];
+        let parents = HashMap::new();
+        let context = StepContext {
+            lifetime_ms: 500,
+            ..context(0.5, &players, &parents)
+        };
+        // Expires right on the player
+        let mut motion = Motion::new(Vector2::new(100.0, 100.0), Vector2::new(0.0, 0.0));
+        let step = integrate(&mut motion, &context);
+        assert_eq!(step.fate, Fate::Expired);
+        assert!(step.player_gap < 0.0);
+        assert!(!step.fate.collides());
+        assert!(Fate::Moving.collides());
+        assert!(!Fate::OffScreen.collides());
+        assert!(!Fate::Burst.collides());
+    }
+
+    #[test]
+    fn bursts_after_its_frames() {
+        let parents = HashMap::new();
+        let mut motion = Motion::new(Vector2::new(100.0, 100.0), Vector2::new(0.0, 0.0));
+        motion.burst = Some(Burst {
+            frames: 3,
+            kind: "orb_bullet".to_string(),
+            count: 8,
+            speed: 50.0,
+            angle: 0.0,
+        });
+        // Frames are counted however long they are
+        for deltatime in [0.01, 0.5] {
+            let context = context(deltatime, &[], &parents);
+            assert_eq!(integrate(&mut motion, &context).fate, Fate::Moving);
+        }
+        let context = context(0.01, &[], &parents);
+        assert_eq!(integrate(&mut motion, &context).fate, Fate::Burst);
+    }
+
+    #[test]
+    fn negative_lifetime_never_expires() {
+        let parents = HashMap::new();
+        let context = context(1.0, &[], &parents);
+        let mut motion = Motion::new(Vector2::new(100.0, 100.0), Vector2::new(0.0, 0.0));
+        for _ in 0..3600 {
+            assert_eq!(integrate(&mut motion, &context).fate, Fate::Moving);
+        }
+    }
+
+    #[test]
+    fn off_screen_past_the_margin() {
+        let parents = HashMap::new();
+        let context = context(1.0 / 60.0, &[], &parents);
+        let fate = |x: f32, y: f32| {
+            let mut motion = Motion::new(Vector2::new(x, y), Vector2::new(0.0, 0.0));
+            integrate(&mut motion, &context).fate
+        };
+        // On the edge of the 16px margin and just past it, on every side
+        assert_eq!(fate(-16.0, 100.0), Fate::Moving);
+        assert_eq!(fate(-16.5, 100.0), Fate::OffScreen);
+        assert_eq!(fate(100.0, -16.0), Fate::Moving);
+        assert_eq!(fate(100.0, -16.5), Fate::OffScreen);
+        assert_eq!(fate(PLAYFIELD_WIDTH + 16.0, 100.0), Fate::Moving);
+        assert_eq!(fate(PLAYFIELD_WIDTH + 16.5, 100.0), Fate::OffScreen);
+        assert_eq!(fate(100.0, PLAYFIELD_HEIGHT + 16.0), Fate::Moving);
+        assert_eq!(fate(100.0, PLAYFIELD_HEIGHT + 16.5), Fate::OffScreen);
+    }
+
+    #[test]
+    fn items_on_the_player_keep_their_velocity() {
+        let players = [PlayerHitbox {
+            position: Vector2::new(100.0, 200.0),
+            radius: 4.0,
+        }];
+        let parents = HashMap::new();
+        let context = StepContext {
+            homing_speed: Some(300.0),
+            ..context(1.0 / 60.0, &players, &parents)
+        };
+        let mut motion = Motion::new(players[0].position, Vector2::new(0.0, 40.0));
+        integrate(&mut motion, &context);
+        assert_eq!((motion.dx, motion.dy), (0.0, 40.0));
+    }
+
+    #[test]
+    fn nested_orbits_keep_their_radius() {
+        // A moving bullet, orbited by a second one, orbited by a third
+        let (moving, still) = (Vector2::new(30.0, 10.0), Vector2::new(0.0, 0.0));
+        let mut bullets = vec![
+            (
+                handle(0, 0),
+                Motion::new(Vector2::new(100.0, 100.0), moving),
+            ),
+            (handle(1, 0), Motion::new(Vector2::new(130.0, 100.0), still)),
+            (handle(2, 0), Motion::new(Vector2::new(140.0, 100.0), still)),
+        ];
+        bullets[1].1.orbit = Some(Orbit {
+            parent: handle(0, 0),
+            radius: 30.0,
+            angle: 0.0,
+            angular_speed: 2.0,
+        });
+        bullets[2].1.orbit = Some(Orbit {
+            parent: handle(1, 0),
+            radius: 10.0,
+            angle: 0.0,
+            angular_speed: -5.0,
+        });
+
+        for _ in 0..120 {
+            let motions = bullets.iter().map(|(handle, x)| (*handle, x)).collect();
+            let parents = orbit_parents(&motions, 1.0 / 60.0);
+            let context = context(1.0 / 60.0, &[], &parents);
+            for (_, motion) in bullets.iter_mut() {
+                integrate(motion, &context);
+            }
+
+            let [first, second, third] = [0, 1, 2].map(|i| bullets[i].1.position);
+            assert!((first.distance_to(second) - 30.0).abs() < 0.001);
+            assert!((second.distance_to(third) - 10.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn bullets_orbiting_each_other() {
+        let mut first = Motion::new(Vector2::new(100.0, 100.0), Vector2::new(10.0, 0.0));
+        let mut second = Motion::new(Vector2::new(120.0, 100.0), Vector2::new(0.0, 0.0));
+        first.orbit = Some(Orbit {
+            parent: handle(1, 0),
+            radius: 20.0,
+            angle: PI,
+            angular_speed: 1.0,
+        });
+        second.orbit = Some(Orbit {
+            parent: handle(0, 0),
+            radius: 20.0,
+            angle: 0.0,
+            angular_speed: 1.0,
+        });
+        let motions = HashMap::from([(handle(0, 0), &first), (handle(1, 0), &second)]);
+        // Gives up following the cycle rather than recursing forever
+        let parents = orbit_parents(&motions, 1.0 / 60.0);
+        assert_eq!(parents.len(), 2);
+        assert!(parents.values().all(|x| x.x.is_finite() && x.y.is_finite()));
+    }
+
+    // Times moving and culling `count` random bullets for `frames` ticks, on the
+    // calling thread and then in parallel.
+    // Returns both durations and wether they left the bullets in the same state
+    fn benchmark(count: usize, frames: u32) -> (Duration, Duration, bool) {
+        let mut rng = Pcg32::new(count as u64, 0);
+        let bullets: Vec<Motion> = (0..count)
+            .map(|_| {
+                let position = Vector2::new(
+                    rng.range_f32(0.0, PLAYFIELD_WIDTH),
+                    rng.range_f32(0.0, PLAYFIELD_HEIGHT),
+                );
+                let angle = rng.range_f32(0.0, 2.0 * PI);
+                let speed = rng.range_f32(20.0, 120.0);
+                Motion::new(position, Vector2::new(angle.cos(), angle.sin()) * speed)
+            })
+            .collect();
+        let parents = HashMap::new();
+        let players = [PlayerHitbox {
+            position: Vector2::new(PLAYFIELD_WIDTH / 2.0, PLAYFIELD_HEIGHT * 0.8),
+            radius: 4.0,
+        }];
+        let context = context(1.0 / 60.0, &players, &parents);
+
+        let run = |parallel: bool| {
+            let mut bullets = bullets.clone();
+            let start = Instant::now();
+            for _ in 0..frames {
+                let steps = integrate_batch(&mut bullets, |x| x, &context, parallel);
+                // Culled the same way as BulletManager, backwards with swap_remove
+                for (i, step) in steps.iter().enumerate().rev() {
+                    if step.fate == Fate::OffScreen {
+                        bullets.swap_remove(i);
+                    }
+                }
+            }
+            (start.elapsed(), bullets)
+        };
+        let (sequential, sequential_bullets) = run(false);
+        let (parallel, parallel_bullets) = run(true);
+        (sequential, parallel, sequential_bullets == parallel_bullets)
+    }
+
+    // Bullets exercising every part of `integrate`, from a fixed seed
+    fn random_bullets(count: usize) -> Vec<Motion> {
+        let mut rng = Pcg32::new(7, 3);
+        (0..count)
+            .map(|i| {
+                let position = Vector2::new(
+                    rng.range_f32(0.0, PLAYFIELD_WIDTH),
+                    rng.range_f32(0.0, PLAYFIELD_HEIGHT),
+                );
+                let angle = rng.range_f32(0.0, 2.0 * PI);
+                let speed = rng.range_f32(20.0, 200.0);
+                let mut motion =
+                    Motion::new(position, Vector2::new(angle.cos(), angle.sin()) * speed);
+                if rng.chance(0.2) {
+                    motion.homing = Some(rng.range_f32(1.0, 6.0));
+                }
+                if rng.chance(0.1) {
+                    motion.burst = Some(Burst {
+                        frames: rng.range_i64(1, 30) as u32,
+                        kind: "orb_bullet".to_string(),
+                        count: 8,
+                        speed: 60.0,
+                        angle: 0.0,
+                    });
+                }
+                if i > 0 && rng.chance(0.1) {
+                    motion.orbit = Some(Orbit {
+                        parent: handle(rng.range_i64(0, i as i64 - 1) as u32, 0),
+                        radius: rng.range_f32(5.0, 30.0),
+                        angle: rng.range_f32(0.0, 2.0 * PI),
+                        angular_speed: rng.range_f32(-4.0, 4.0),
+                    });
+                }
+                motion
+            })
+            .collect()
+    }
+
+    // Runs `frames` ticks of `integrate_batch`, returning every step and the final state
+    fn simulate(parallel: bool, frames: u32) -> (Vec<Vec<Step>>, Vec<Motion>) {
+        let mut bullets = random_bullets(4 * PARALLEL_CHUNK + 17);
+        let players = [
+            PlayerHitbox {
+                position: Vector2::new(200.0, 220.0),
+                radius: 4.0,
+            },
+            PlayerHitbox {
+                position: Vector2::new(280.0, 220.0),
+                radius: 3.0,
+            },
+        ];
+        let targets = [Vector2::new(240.0, 40.0), Vector2::new(100.0, 60.0)];
+        let mut steps = vec![];
+        for _ in 0..frames {
+            let motions = bullets
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (handle(i as u32, 0), x))
+                .collect();
+            let parents = orbit_parents(&motions, 1.0 / 60.0);
+            let context = StepContext {
+                lifetime_ms: 2000,
+                targets: &targets,
+                ..context(1.0 / 60.0, &players, &parents)
+            };
+            steps.push(integrate_batch(&mut bullets, |x| x, &context, parallel));
+        }
+        (steps, bullets)
+    }
+
+    #[test]
+    fn parallel_integration_is_deterministic() {
+        let expected = simulate(false, 90);
+        assert_eq!(simulate(true, 90), expected);
+
+        // Independent of the amount of threads the batch is split between
+        for threads in [1, 2, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            assert_eq!(pool.install(|| simulate(true, 90)), expected);
+            assert_eq!(pool.install(|| simulate(false, 90)), expected);
+        }
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn benchmark_large_batches() {
+        for count in [10_000, 50_000] {
+            let (sequential, parallel, same) = benchmark(count, 600);
+            println!(
+                "{count} bullets, 600 frames: sequential {:.2} ms, parallel {:.2} ms on {} threads",
+                sequential.as_secs_f64() * 1000.0,
+                parallel.as_secs_f64() * 1000.0,
+                rayon::current_num_threads(),
+            );
+            assert!(same);
+        }
+    }
+
+    fn direction(motion: &Motion) -> f32 {
+        motion.dy.atan2(motion.dx)
+    }
+
+    fn speed(motion: &Motion) -> f32 {
+        Vector2::new(motion.dx, motion.dy).length()
+    }
+
+    #[test]
+    fn steering_turns_at_most_max_turn() {
+        let pos = Vector2::new(0.0, 0.0);
+        // Target a quarter turn away
+        let mut motion = Motion::new(pos, Vector2::new(100.0, 0.0));
+        steer(&mut motion, pos, 0.1, &[Vector2::new(0.0, 50.0)]);
+        assert!((direction(&motion) - 0.1).abs() < 1e-5);
+        assert!((speed(&motion) - 100.0).abs() < 1e-3);
+
+        // Heads straight for a target within reach
+        let mut motion = Motion::new(pos, Vector2::new(100.0, 0.0));
+        steer(&mut motion, pos, 0.5, &[Vector2::new(50.0, 10.0)]);
+        assert!((direction(&motion) - 0.2f32.atan()).abs() < 1e-5);
+        assert!((speed(&motion) - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn steering_goes_the_short_way_round() {
+        let pos = Vector2::new(0.0, 0.0);
+        // Heading just above -x, towards a target just below it, across +-PI
+        let angle = PI - 0.05;
+        let velocity = Vector2::new(angle.cos(), angle.sin()) * 200.0;
+        let mut motion = Motion::new(pos, velocity);
+        let target = Vector2::new((-angle).cos(), (-angle).sin()) * 50.0;
+        steer(&mut motion, pos, 0.06, &[target]);
+
+        // Turned 0.06 radians on through PI, not nearly a full turn the other way
+        let expected = -PI + 0.01;
+        assert!((direction(&motion) - expected).abs() < 1e-4);
+        assert!((speed(&motion) - 200.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn steering_without_targets_keeps_the_velocity() {
+        let pos = Vector2::new(10.0, 10.0);
+        let mut motion = Motion::new(pos, Vector2::new(30.0, 40.0));
+        steer(&mut motion, pos, 1.0, &[]);
+        assert_eq!((motion.dx, motion.dy), (30.0, 40.0));
+        // Nor when already on the target
+        steer(&mut motion, pos, 1.0, &[pos]);
+        assert_eq!((motion.dx, motion.dy), (30.0, 40.0));
+    }
+
+    #[test]
+    fn every_player_along_the_path_is_in_reach() {
+        // Both players stand in the way of the bullet, the first one squarely
+        let players = [
+            PlayerHitbox {
+                position: Vector2::new(100.0, 60.0),
+                radius: 4.0,
+            },
+            PlayerHitbox {
+                position: Vector2::new(102.0, 90.0),
+                radius: 4.0,
+            },
+        ];
+        let parents = HashMap::new();
+        let mut motion = Motion::new(Vector2::new(100.0, 50.0), Vector2::new(0.0, 600.0));
+        let step = integrate(&mut motion, &context(0.1, &players, &parents));
+
+        assert_eq!(step.player, Some(0));
+        let touching = players_in_reach(&players, step.previous, motion.position, 5.0);
+        assert_eq!(touching, vec![0, 1]);
+    }
+
+    #[test]
+    fn nearest_player_along_the_path_comes_first() {
+        // Player 2 is hit dead-on, player 1 only grazed by the bullet
+        let players = [
+            PlayerHitbox {
+                position: Vector2::new(104.0, 60.0),
+                radius: 4.0,
+            },
+            PlayerHitbox {
+                position: Vector2::new(100.0, 90.0),
+                radius: 4.0,
+            },
+        ];
+        let parents = HashMap::new();
+        let mut motion = Motion::new(Vector2::new(100.0, 50.0), Vector2::new(0.0, 600.0));
+        let step = integrate(&mut motion, &context(0.1, &players, &parents));
+
+        assert_eq!(step.player, Some(1));
+        let touching = players_in_reach(&players, step.previous, motion.position, 5.0);
+        assert_eq!(touching, vec![1, 0]);
+    }
+}
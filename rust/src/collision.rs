@@ -0,0 +1,66 @@
+use gdnative::prelude::*;
+
+// Shortest distance from `point` to the segment from `a` to `b`
+pub fn segment_distance(point: Vector2, a: Vector2, b: Vector2) -> f32 {
+    let segment = b - a;
+    let length_squared = segment.dot(segment);
+    if length_squared == 0.0 {
+        return point.distance_to(a);
+    }
+    let t = ((point - a).dot(segment) / length_squared).clamp(0.0, 1.0);
+    point.distance_to(a + segment * t)
+}
+
+// Wether a circle touches any segment of a polyline
+pub fn polyline_touches_circle(points: &[Vector2], center: Vector2, radius: f32) -> bool {
+    points
+        .windows(2)
+        .any(|x| segment_distance(center, x[0], x[1]) <= radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_to_segment() {
+        let a = Vector2::new(0.0, 0.0);
+        let b = Vector2::new(10.0, 0.0);
+        // Beside, before and after the segment
+        assert_eq!(segment_distance(Vector2::new(5.0, 3.0), a, b), 3.0);
+        assert_eq!(segment_distance(Vector2::new(-4.0, 3.0), a, b), 5.0);
+        assert_eq!(segment_distance(Vector2::new(13.0, 4.0), a, b), 5.0);
+        // A zero length segment is a point
+        assert_eq!(segment_distance(Vector2::new(3.0, 4.0), a, a), 5.0);
+    }
+
+    #[test]
+    fn fast_bullet_crossing_hitbox() {
+        // A bullet moving at 600 px/sec over a long 0.1 sec frame
+        let player = Vector2::new(100.0, 80.0);
+        let reach = 4.0 + 5.0; // Player hitbox and bullet radius
+        let from = Vector2::new(100.0, 50.0);
+        let to = from + Vector2::new(0.0, 600.0) * 0.1;
+
+        // Checking only where the bullet is would let it pass through
+        assert!(from.distance_to(player) > reach);
+        assert!(to.distance_to(player) > reach);
+        // The path in between crosses the hitbox
+        assert!(segment_distance(player, from, to) <= reach);
+    }
+
+    #[test]
+    fn polyline_touches() {
+        let points = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(10.0, 10.0),
+        ];
+        let near = Vector2::new(12.0, 5.0);
+        let inside_corner = Vector2::new(5.0, 5.0);
+        assert!(polyline_touches_circle(&points, near, 2.0));
+        assert!(!polyline_touches_circle(&points, inside_corner, 2.0));
+        // A single point has no segments to touch
+        assert!(!polyline_touches_circle(&points[..1], points[0], 2.0));
+    }
+}
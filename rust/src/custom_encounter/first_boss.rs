@@ -1,38 +1,170 @@
 use gdnative::prelude::*;
 
-use super::generic_encounter::GenericEncounter;
+use super::generic_encounter::{self, BossStatus, EndPolicy, GenericEncounter, StartTrigger};
+use crate::collision;
+use crate::difficulty::DifficultyProfile;
+use crate::rank::RankScale;
+use crate::save_state::{EncounterSnapshot, FirstBossSnapshot, SaveStateError};
+use crate::signals;
 
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
-pub struct FirstBoss {}
+#[register_with(Self::register)]
+pub struct FirstBoss {
+    // Health of the boss, the encounter ends when it reaches 0
+    #[property(default = 100)]
+    max_health: u32,
+    // Time (msec) to defeat the boss before the encounter ends
+    // -1 = infinite
+    #[property(default = -1)]
+    time_limit_ms: i64,
+    // Phases split the boss's health evenly, used by practice mode
+    #[property(default = 1)]
+    phase_count: u32,
+    // Radius of the boss's hitbox around its position
+    #[property(default = 24.0)]
+    hitbox_radius: f32,
+
+    health: u32,
+    // Simulation time since the encounter started (msec)
+    time: f32,
+}
 
 #[methods]
 impl FirstBoss {
     fn new(_owner: &Node2D) -> Self {
-        Self::default()
+        Self {
+            max_health: 100,
+            time_limit_ms: -1,
+            phase_count: 1,
+            hitbox_radius: 24.0,
+            ..Default::default()
+        }
+    }
+
+    fn register(builder: &ClassBuilder<Self>) {
+        generic_encounter::register_signals(builder);
     }
 }
 
 impl GenericEncounter for FirstBoss {
-    fn activate(&mut self, owner: &Node2D) {
+    fn on_pending(&mut self, owner: &Node2D) {
+        owner.set_visible(false);
+        self.health = self.max_health;
+    }
+    fn on_entering(&mut self, owner: &Node2D) {
         owner.set_visible(true);
+        self.health = self.max_health;
+        self.time = 0.0;
+        signals::emit_deferred(owner, "encounter_started", &[]);
     }
-    fn deactivate(&mut self, owner: &Node2D) {
+    fn on_active(&mut self, _owner: &Node2D) {}
+    fn on_ending(&mut self, _owner: &Node2D) {}
+    fn on_finished(&mut self, owner: &Node2D) {
         owner.set_visible(false);
+        signals::emit_deferred(owner, "encounter_ended", &[]);
     }
 
+    fn in_position(&self) -> bool {
+        true
+    }
     fn has_ended(&self) -> bool {
-        false
+        self.health == 0
+            || (self.time_limit_ms != -1 && self.time >= self.time_limit_ms as f32)
     }
     fn end_delay(&self) -> i64 {
         1000
     }
+    fn end_policy(&self) -> EndPolicy {
+        // Defeating a boss clears the screen
+        EndPolicy::CancelBullets { to_items: true }
+    }
+    fn start_trigger(&self) -> StartTrigger {
+        StartTrigger::PreviousEnded
+    }
+    fn killed_fraction(&self) -> f32 {
+        if self.health == 0 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+    fn boss_status(&self) -> Option<BossStatus> {
+        Some(BossStatus {
+            health: self.health,
+            max_health: self.max_health,
+            time_remaining_ms: if self.time_limit_ms == -1 {
+                -1
+            } else {
+                (self.time_limit_ms - self.time as i64).max(0)
+            },
+        })
+    }
+
+    fn tick(&mut self, _owner: &Node2D, _rank: RankScale, deltatime: f32) {
+        self.time += deltatime * 1000.0;
+    }
 
-    fn tick(&mut self, _owner: &Node2D, _deltatime: f32) {
+    fn hit_enemy(
+        &mut self,
+        owner: &Node2D,
+        from: Vector2,
+        to: Vector2,
+        radius: u32,
+    ) -> bool {
+        // Defeated bosses let bullets pass
+        if self.health == 0 {
+            return false;
+        }
+        let distance = collision::segment_distance(owner.global_position(), from, to);
+        if distance > self.hitbox_radius + radius as f32 {
+            return false;
+        }
+        self.health -= 1;
+        true
+    }
 
+    fn enemy_positions(&self, owner: &Node2D) -> Vec<Vector2> {
+        if self.health > 0 {
+            vec![owner.global_position()]
+        } else {
+            vec![]
+        }
     }
 
-    fn hit_enemy(&mut self, _owner: &Node2D, _pos: Vector2, _radius: u32) -> bool {
+    fn set_difficulty(&mut self, _owner: &Node2D, profile: DifficultyProfile) {
+        self.max_health = profile.health(self.max_health);
+    }
+
+    fn skip_to_phase(&mut self, _owner: &Node2D, phase: u32) -> bool {
+        let phase_count = self.phase_count.max(1);
+        if phase >= phase_count {
+            return false;
+        }
+        // Start with the health left at the beginning of the phase
+        self.health = self.max_health * (phase_count - phase) / phase_count;
         true
     }
+
+    fn snapshot(&self, _owner: &Node2D) -> EncounterSnapshot {
+        EncounterSnapshot::FirstBoss(FirstBossSnapshot {
+            health: self.health,
+            time: self.time,
+        })
+    }
+
+    fn restore(
+        &mut self,
+        _owner: &Node2D,
+        snapshot: &EncounterSnapshot,
+    ) -> Result<(), SaveStateError> {
+        match snapshot {
+            EncounterSnapshot::FirstBoss(snapshot) => {
+                self.health = snapshot.health;
+                self.time = snapshot.time;
+                Ok(())
+            }
+            _ => Err(SaveStateError::Mismatch("Expected a FirstBoss".to_string())),
+        }
+    }
 }
\ No newline at end of file
@@ -1,13 +1,151 @@
 use gdnative::prelude::*;
 
+use crate::bullet_manager::{BulletManager, Faction};
+use crate::difficulty::DifficultyProfile;
+use crate::rank::RankScale;
+use crate::save_state::{EncounterSnapshot, SaveStateError};
+
+// How an encounter finishes once its enemies are gone (or it timed out)
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum EndPolicy {
+    // End straight away, leaving enemy bullets in play
+    #[default]
+    Immediate,
+    // Wait for every enemy bullet to leave the screen or hit the player
+    WaitForClear,
+    // Cancel every enemy bullet, optionally turning them into score items
+    CancelBullets { to_items: bool },
+}
+
+impl EndPolicy {
+    // Parses the policy names used by exported properties,
+    // "immediate", "wait_for_clear" and "cancel_bullets"
+    pub fn from_name(name: &str, to_items: bool) -> EndPolicy {
+        match name {
+            "wait_for_clear" => EndPolicy::WaitForClear,
+            "cancel_bullets" => EndPolicy::CancelBullets { to_items },
+            name => {
+                if name != "immediate" {
+                    godot_warn!("Unknown end policy {name}, using immediate");
+                }
+                EndPolicy::Immediate
+            }
+        }
+    }
+
+    // Applies the policy to the bullets in play.
+    // Returns true once the encounter is allowed to end
+    pub fn apply(&self, bullet_manager: &TInstance<'static, BulletManager, Shared>) -> bool {
+        match self {
+            EndPolicy::Immediate => true,
+            EndPolicy::WaitForClear => bullet_manager
+                .map(|x: &BulletManager, _node: TRef<Node2D>| x.live_count(Faction::Enemy) == 0)
+                .unwrap(),
+            EndPolicy::CancelBullets { to_items } => {
+                bullet_manager
+                    .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                        x.cancel_enemy_bullets(node.as_ref(), *to_items)
+                    })
+                    .unwrap();
+                true
+            }
+        }
+    }
+}
+
+// Condition for the EncounterManager to start an encounter.
+// Encounters always start in order, after the previous one has started
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum StartTrigger {
+    // Once the previous encounter has finished (including its end delay)
+    #[default]
+    PreviousEnded,
+    // Once `ms` have passed since the stage started
+    Time { ms: i64 },
+    // Once `percent` of the previous encounter's enemies have been killed
+    PreviousKilled { percent: f32 },
+}
+
+impl StartTrigger {
+    // Parses the trigger names used by exported properties,
+    // "previous_ended", "time" and "previous_killed"
+    pub fn from_name(name: &str, ms: i64, percent: f32) -> StartTrigger {
+        match name {
+            "time" => StartTrigger::Time { ms },
+            "previous_killed" => StartTrigger::PreviousKilled { percent },
+            name => {
+                if name != "previous_ended" {
+                    godot_warn!("Unknown start trigger {name}, using previous_ended");
+                }
+                StartTrigger::PreviousEnded
+            }
+        }
+    }
+}
+
+// Health and timer of a boss encounter, shown on the HUD
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct BossStatus {
+    pub health: u32,
+    pub max_health: u32,
+    // -1 when the boss has no time limit
+    pub time_remaining_ms: i64,
+}
+
+// Signals emitted by every encounter, registered from each encounter's `register`
+// - encounter_started: Pending -> Entering
+// - encounter_ended: Ending -> Finished
+pub fn register_signals<T: NativeClass>(builder: &ClassBuilder<T>) {
+    builder.signal("encounter_started").done();
+    builder.signal("encounter_ended").done();
+}
+
 pub trait GenericEncounter {
-    fn activate(&mut self, owner: &Node2D);
-    fn deactivate(&mut self, owner: &Node2D);
+    // Lifecycle hooks, called by the EncounterManager as the
+    // encounter moves through each EncounterState
+    // - Pending, at startup or when restarted
+    fn on_pending(&mut self, owner: &Node2D);
+    // - Pending -> Entering
+    fn on_entering(&mut self, owner: &Node2D);
+    // - Entering -> Active
+    fn on_active(&mut self, owner: &Node2D);
+    // - Active -> Ending
+    fn on_ending(&mut self, owner: &Node2D);
+    // - Ending -> Finished
+    fn on_finished(&mut self, owner: &Node2D);
 
+    // Wether every enemy has moved into position (Entering -> Active)
+    fn in_position(&self) -> bool;
+    // Wether the encounter is over (Active -> Ending)
     fn has_ended(&self) -> bool;
     fn end_delay(&self) -> i64;
+    // What happens to enemy bullets when the encounter ends
+    fn end_policy(&self) -> EndPolicy;
+    // When the EncounterManager should start the encounter
+    fn start_trigger(&self) -> StartTrigger;
+    // Fraction (0.0 - 1.0) of enemies killed so far
+    fn killed_fraction(&self) -> f32;
+    // Health and timer for boss encounters, None for regular encounters
+    fn boss_status(&self) -> Option<BossStatus>;
+
+    fn tick(&mut self, owner: &Node2D, rank: RankScale, deltatime: f32);
+
+    // Called with the path a player bullet travelled this tick,
+    // returns true if it hit an enemy
+    fn hit_enemy(&mut self, owner: &Node2D, from: Vector2, to: Vector2, radius: u32) -> bool;
+    // Global positions of the enemies that can still be hit, used for homing shots
+    fn enemy_positions(&self, owner: &Node2D) -> Vec<Vector2>;
+
+    // Scales the encounter's enemies, called once before the stage starts
+    fn set_difficulty(&mut self, owner: &Node2D, profile: DifficultyProfile);
 
-    fn tick(&mut self, owner: &Node2D, deltatime: f32);
+    // Used by practice mode to start partway through a boss, called after `on_entering`.
+    // Returns false if the encounter doesn't have that phase
+    fn skip_to_phase(&mut self, owner: &Node2D, phase: u32) -> bool;
 
-    fn hit_enemy(&mut self, owner: &Node2D, pos: Vector2, radius: u32) -> bool;
-}
\ No newline at end of file
+    // Simulation state for save states
+    fn snapshot(&self, owner: &Node2D) -> EncounterSnapshot;
+    // Fails without changing anything if the snapshot is for a different encounter
+    fn restore(&mut self, owner: &Node2D, snapshot: &EncounterSnapshot)
+        -> Result<(), SaveStateError>;
+}
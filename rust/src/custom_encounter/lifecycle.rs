@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use crate::save_state::LifecycleSnapshot;
+
+// States an encounter moves through, in order
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum EncounterState {
+    // Waiting for its StartTrigger, hidden
+    Pending,
+    // Started, enemies are moving into position
+    Entering,
+    // Every enemy is in position
+    Active,
+    // Enemies are gone (or timed out), waiting out the end delay
+    Ending,
+    // Done, hidden and no longer ticked
+    Finished,
+}
+
+// State machine for a single encounter, driven by the EncounterManager.
+// Timing uses simulation time so the end delay follows the game clock
+pub struct Lifecycle {
+    state: EncounterState,
+    // Simulation time spent in the current state (secs)
+    time_in_state: f32,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            state: EncounterState::Pending,
+            time_in_state: 0.0,
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn state(&self) -> EncounterState {
+        self.state
+    }
+
+    // True while the encounter should be ticked
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.state,
+            EncounterState::Entering | EncounterState::Active | EncounterState::Ending
+        )
+    }
+
+    // Pending -> Entering
+    // Returns false if the encounter had already started
+    pub fn start(&mut self) -> bool {
+        if self.state == EncounterState::Pending {
+            self.transition(EncounterState::Entering);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Advances the state machine by `deltatime`, moving at most one state forward.
+    // Returns the new state when a transition happened
+    pub fn update(
+        &mut self,
+        deltatime: f32,
+        in_position: bool,
+        ended: bool,
+        end_delay_ms: i64,
+    ) -> Option<EncounterState> {
+        self.time_in_state += deltatime;
+
+        let next = match self.state {
+            EncounterState::Entering if in_position || ended => EncounterState::Active,
+            EncounterState::Active if ended => EncounterState::Ending,
+            EncounterState::Ending if self.time_in_state * 1000.0 >= end_delay_ms as f32 => {
+                EncounterState::Finished
+            }
+            _ => return None,
+        };
+
+        self.transition(next);
+        Some(next)
+    }
+
+    // Moves straight to Finished, used when skipping an encounter
+    pub fn skip(&mut self) {
+        self.transition(EncounterState::Finished);
+    }
+
+    // Returns to Pending, used when restarting an encounter
+    pub fn reset(&mut self) {
+        self.transition(EncounterState::Pending);
+    }
+
+    pub fn snapshot(&self) -> LifecycleSnapshot {
+        LifecycleSnapshot {
+            state: self.state,
+            time_in_state: self.time_in_state,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &LifecycleSnapshot) {
+        self.state = snapshot.state;
+        self.time_in_state = snapshot.time_in_state;
+    }
+
+    fn transition(&mut self, state: EncounterState) {
+        self.state = state;
+        self.time_in_state = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ticks of 1/60 secs
+    const TICK: f32 = 1.0 / 60.0;
+
+    fn active() -> Lifecycle {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.start();
+        lifecycle.update(TICK, true, false, 0);
+        lifecycle
+    }
+
+    #[test]
+    fn moves_through_every_state_in_order() {
+        let mut lifecycle = Lifecycle::default();
+        assert_eq!(lifecycle.state(), EncounterState::Pending);
+        assert!(!lifecycle.is_running());
+
+        assert!(lifecycle.start());
+        assert_eq!(lifecycle.state(), EncounterState::Entering);
+        assert!(lifecycle.is_running());
+
+        assert_eq!(lifecycle.update(TICK, false, false, 100), None);
+        assert_eq!(
+            lifecycle.update(TICK, true, false, 100),
+            Some(EncounterState::Active)
+        );
+        assert_eq!(lifecycle.update(TICK, true, false, 100), None);
+        assert_eq!(
+            lifecycle.update(TICK, true, true, 100),
+            Some(EncounterState::Ending)
+        );
+        assert!(lifecycle.is_running());
+
+        assert_eq!(lifecycle.update(0.05, true, true, 100), None);
+        assert_eq!(
+            lifecycle.update(0.05, true, true, 100),
+            Some(EncounterState::Finished)
+        );
+        assert!(!lifecycle.is_running());
+        assert_eq!(lifecycle.update(1.0, true, true, 100), None);
+    }
+
+    #[test]
+    fn start_only_leaves_pending() {
+        let mut lifecycle = active();
+        assert!(!lifecycle.start());
+        assert_eq!(lifecycle.state(), EncounterState::Active);
+    }
+
+    #[test]
+    fn pending_waits_for_start() {
+        let mut lifecycle = Lifecycle::default();
+        assert_eq!(lifecycle.update(10.0, true, true, 0), None);
+        assert_eq!(lifecycle.state(), EncounterState::Pending);
+    }
+
+    #[test]
+    fn ending_while_entering_still_becomes_active_first() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.start();
+        assert_eq!(
+            lifecycle.update(TICK, false, true, 0),
+            Some(EncounterState::Active)
+        );
+        assert_eq!(
+            lifecycle.update(TICK, false, true, 0),
+            Some(EncounterState::Ending)
+        );
+    }
+
+    #[test]
+    fn end_delay_runs_on_simulation_time() {
+        let mut lifecycle = active();
+        lifecycle.update(TICK, true, true, 500);
+        assert_eq!(lifecycle.state(), EncounterState::Ending);
+
+        // A paused game ticks with a deltatime of 0, which never finishes the delay
+        for _ in 0..1000 {
+            assert_eq!(lifecycle.update(0.0, true, true, 500), None);
+        }
+        // Half speed takes twice as many ticks as normal speed
+        for _ in 0..59 {
+            assert_eq!(lifecycle.update(TICK / 2.0, true, true, 500), None);
+        }
+        let mut ticks = 59;
+        while lifecycle.update(TICK / 2.0, true, true, 500).is_none() {
+            ticks += 1;
+        }
+        assert_eq!(lifecycle.state(), EncounterState::Finished);
+        assert!((59..=61).contains(&ticks));
+    }
+
+    #[test]
+    fn the_delay_starts_when_ending_begins() {
+        let mut lifecycle = active();
+        // Time spent active doesn't count towards the end delay
+        lifecycle.update(10.0, true, false, 500);
+        lifecycle.update(TICK, true, true, 500);
+        assert_eq!(lifecycle.update(0.25, true, true, 500), None);
+        assert_eq!(
+            lifecycle.update(0.25, true, true, 500),
+            Some(EncounterState::Finished)
+        );
+    }
+
+    #[test]
+    fn skip_finishes_from_any_state() {
+        for mut lifecycle in [Lifecycle::default(), active()] {
+            lifecycle.skip();
+            assert_eq!(lifecycle.state(), EncounterState::Finished);
+            assert!(!lifecycle.is_running());
+            assert!(!lifecycle.start());
+        }
+    }
+
+    #[test]
+    fn reset_returns_to_pending() {
+        let mut lifecycle = active();
+        lifecycle.update(TICK, true, true, 1000);
+        lifecycle.update(0.5, true, true, 1000);
+        lifecycle.reset();
+        assert_eq!(lifecycle.state(), EncounterState::Pending);
+        assert_eq!(lifecycle.time_in_state, 0.0);
+
+        // And can run again from the start
+        assert!(lifecycle.start());
+        assert_eq!(lifecycle.state(), EncounterState::Entering);
+    }
+
+    #[test]
+    fn snapshot_restores_the_state_and_its_time() {
+        let mut lifecycle = active();
+        lifecycle.update(TICK, true, true, 1000);
+        lifecycle.update(0.75, true, true, 1000);
+
+        let mut restored = Lifecycle::default();
+        restored.restore(&lifecycle.snapshot());
+        assert_eq!(restored.state(), EncounterState::Ending);
+        assert_eq!(
+            restored.update(0.25, true, true, 1000),
+            Some(EncounterState::Finished)
+        );
+    }
+}
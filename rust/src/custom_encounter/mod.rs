@@ -1,6 +1,7 @@
 use gdnative::prelude::*;
 
 pub mod generic_encounter;
+pub mod lifecycle;
 pub mod first_boss;
 
 pub fn register(handle: &InitHandle) {
@@ -0,0 +1,275 @@
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::enemy::generic_enemy::GenericEnemy;
+
+// Difficulty selected at the start of a run
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Lunatic,
+}
+
+impl Difficulty {
+    // Parses the names used by properties, "easy", "normal", "hard" or "lunatic"
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            "lunatic" => Some(Difficulty::Lunatic),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Lunatic => "lunatic",
+        }
+    }
+
+    pub fn profile(&self) -> DifficultyProfile {
+        match self {
+            Difficulty::Easy => DifficultyProfile {
+                bullet_speed: 0.8,
+                bullet_density: 0.6,
+                fire_rate: 0.75,
+                health: 0.75,
+            },
+            Difficulty::Normal => DifficultyProfile {
+                bullet_speed: 1.0,
+                bullet_density: 1.0,
+                fire_rate: 1.0,
+                health: 1.0,
+            },
+            Difficulty::Hard => DifficultyProfile {
+                bullet_speed: 1.2,
+                bullet_density: 1.4,
+                fire_rate: 1.25,
+                health: 1.25,
+            },
+            Difficulty::Lunatic => DifficultyProfile {
+                bullet_speed: 1.4,
+                bullet_density: 2.0,
+                fire_rate: 1.5,
+                health: 1.5,
+            },
+        }
+    }
+}
+
+// Multipliers applied to every enemy's attack patterns and health
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DifficultyProfile {
+    pub bullet_speed: f32,
+    // Amount of bullets in each attack
+    pub bullet_density: f32,
+    // Attacks per second
+    pub fire_rate: f32,
+    pub health: f32,
+}
+
+impl Default for DifficultyProfile {
+    fn default() -> Self {
+        Difficulty::Normal.profile()
+    }
+}
+
+impl DifficultyProfile {
+    // Replaces the multipliers an enemy has overridden
+    pub fn with_overrides(self, overrides: &DifficultyOverrides) -> Self {
+        let pick = |value: f32, profile: f32| if value < 0.0 { profile } else { value };
+        Self {
+            bullet_speed: pick(overrides.bullet_speed, self.bullet_speed),
+            bullet_density: pick(overrides.bullet_density, self.bullet_density),
+            fire_rate: pick(overrides.fire_rate, self.fire_rate),
+            health: pick(overrides.health, self.health),
+        }
+    }
+
+    pub fn bullet_speed(&self, speed: f32) -> f32 {
+        speed * self.bullet_speed
+    }
+
+    // Always at least one bullet
+    pub fn bullet_count(&self, count: u32) -> u32 {
+        ((count as f32 * self.bullet_density).round() as u32).max(1)
+    }
+
+    // Always an odd amount of bullets, so a spread aimed at the player keeps its
+    // middle bullet on them
+    pub fn aimed_bullet_count(&self, count: u32) -> u32 {
+        let pairs = (count as f32 * self.bullet_density - 1.0) / 2.0;
+        pairs.round().max(0.0) as u32 * 2 + 1
+    }
+
+    pub fn attack_timeout(&self, timeout_ms: i64) -> i64 {
+        (timeout_ms as f32 / self.fire_rate.max(0.01)) as i64
+    }
+
+    // Always at least one health
+    pub fn health(&self, health: u32) -> u32 {
+        ((health as f32 * self.health).round() as u32).max(1)
+    }
+}
+
+// Per-enemy replacements for the multipliers of the profile
+// -1 = use the profile
+#[derive(Clone, Copy)]
+pub struct DifficultyOverrides {
+    pub bullet_speed: f32,
+    pub bullet_density: f32,
+    pub fire_rate: f32,
+    pub health: f32,
+}
+
+impl Default for DifficultyOverrides {
+    fn default() -> Self {
+        Self {
+            bullet_speed: -1.0,
+            bullet_density: -1.0,
+            fire_rate: -1.0,
+            health: -1.0,
+        }
+    }
+}
+
+// Registers the overrides as properties of an enemy
+pub fn register_overrides<T>(builder: &ClassBuilder<T>)
+where
+    T: GenericEnemy<Base = Node2D>,
+{
+    builder
+        .property::<f32>("difficulty/bullet_speed")
+        .with_default(-1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.difficulty_overrides().bullet_speed)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.difficulty_overrides_mut().bullet_speed = v
+        })
+        .done();
+    builder
+        .property::<f32>("difficulty/bullet_density")
+        .with_default(-1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.difficulty_overrides().bullet_density)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.difficulty_overrides_mut().bullet_density = v
+        })
+        .done();
+    builder
+        .property::<f32>("difficulty/fire_rate")
+        .with_default(-1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.difficulty_overrides().fire_rate)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.difficulty_overrides_mut().fire_rate = v
+        })
+        .done();
+    builder
+        .property::<f32>("difficulty/health")
+        .with_default(-1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.difficulty_overrides().health)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.difficulty_overrides_mut().health = v
+        })
+        .done();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for difficulty in [
+            Difficulty::Easy,
+            Difficulty::Normal,
+            Difficulty::Hard,
+            Difficulty::Lunatic,
+        ] {
+            assert_eq!(Difficulty::from_name(difficulty.name()), Some(difficulty));
+        }
+        assert_eq!(Difficulty::from_name("extra"), None);
+    }
+
+    #[test]
+    fn normal_leaves_patterns_unchanged() {
+        let profile = DifficultyProfile::default();
+        assert_eq!(profile.bullet_speed(100.0), 100.0);
+        assert_eq!(profile.bullet_count(9), 9);
+        assert_eq!(profile.aimed_bullet_count(3), 3);
+        assert_eq!(profile.attack_timeout(1000), 1000);
+        assert_eq!(profile.health(10), 10);
+    }
+
+    #[test]
+    fn harder_profiles_scale_up() {
+        let easy = Difficulty::Easy.profile();
+        let lunatic = Difficulty::Lunatic.profile();
+        assert!(easy.bullet_count(9) < 9);
+        assert!(lunatic.bullet_count(9) > 9);
+        assert!(easy.attack_timeout(1000) > 1000);
+        assert!(lunatic.attack_timeout(1000) < 1000);
+        assert!(easy.health(10) < 10);
+        assert!(lunatic.health(10) > 10);
+    }
+
+    #[test]
+    fn never_drops_to_zero() {
+        let profile = DifficultyProfile {
+            bullet_speed: 1.0,
+            bullet_density: 0.0,
+            fire_rate: 0.0,
+            health: 0.0,
+        };
+        assert_eq!(profile.bullet_count(3), 1);
+        assert_eq!(profile.aimed_bullet_count(3), 1);
+        assert_eq!(profile.health(3), 1);
+        // Doesn't divide by zero
+        assert!(profile.attack_timeout(1000) > 0);
+    }
+
+    #[test]
+    fn aimed_spreads_stay_odd() {
+        for difficulty in [
+            Difficulty::Easy,
+            Difficulty::Normal,
+            Difficulty::Hard,
+            Difficulty::Lunatic,
+        ] {
+            assert_eq!(difficulty.profile().aimed_bullet_count(3) % 2, 1);
+        }
+    }
+
+    #[test]
+    fn overrides_replace_only_what_is_set() {
+        let overrides = DifficultyOverrides {
+            health: 2.0,
+            ..Default::default()
+        };
+        let profile = Difficulty::Hard.profile().with_overrides(&overrides);
+        assert_eq!(profile.health, 2.0);
+        assert_eq!(
+            profile.bullet_speed,
+            Difficulty::Hard.profile().bullet_speed
+        );
+        assert_eq!(
+            profile.bullet_density,
+            Difficulty::Hard.profile().bullet_density
+        );
+        assert_eq!(profile.fire_rate, Difficulty::Hard.profile().fire_rate);
+    }
+
+    #[test]
+    fn default_overrides_keep_the_profile() {
+        let profile = Difficulty::Easy.profile();
+        assert_eq!(
+            profile.with_overrides(&DifficultyOverrides::default()),
+            profile
+        );
+    }
+}
@@ -1,16 +1,58 @@
 use gdnative::prelude::*;
-use gdnative::api::OS;
 use gdnative::export::user_data::{Map, MapMut};
 
 use crate::bullet_manager::BulletManager;
-use crate::custom_encounter::generic_encounter::GenericEncounter;
+use crate::bullet_motion::PlayerHitbox;
+use crate::collision;
+use crate::difficulty::DifficultyProfile;
+use crate::custom_encounter::generic_encounter::{
+    self, BossStatus, EndPolicy, GenericEncounter, StartTrigger,
+};
+use crate::encounter_manager;
 use crate::enemy::*;
-use generic_enemy::GenericEnemy;
-use crate::player::Player;
+use generic_enemy::{GenericEnemy, Targets};
+use crate::node_paths;
+use crate::player::{self, Player};
+use crate::rank::RankScale;
+use crate::save_state::{
+    self, EncounterSnapshot, EnemyNodeSnapshot, GenericEncounterSnapshot, SaveStateError,
+};
+use crate::signals;
+
+// Tally of enemy states produced while ticking an encounter
+#[derive(Default, Clone, Copy)]
+struct EnemyCounts {
+    remaining: usize,
+    // Alive enemies still moving into position
+    entering: usize,
+    killed: usize,
+    escaped: usize,
+}
+
+impl std::ops::Add for EnemyCounts {
+    type Output = EnemyCounts;
+
+    fn add(self, other: EnemyCounts) -> EnemyCounts {
+        EnemyCounts {
+            remaining: self.remaining + other.remaining,
+            entering: self.entering + other.entering,
+            killed: self.killed + other.killed,
+            escaped: self.escaped + other.escaped,
+        }
+    }
+}
+
+impl EnemyCounts {
+    // Wether every enemy has been killed or has escaped
+    fn cleared(&self) -> bool {
+        self.remaining == 0
+    }
+}
 
 // TODO: Change implementation to be ontop of GenericEncounter
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct Encounter {
     // Enemy variants
     // TODO: Try replacing with HashMap<TypeId, Vec<Box<Any>>>
@@ -23,111 +65,214 @@ pub struct Encounter {
     small_orbs: Vec<TInstance<'static, small_orb::SmallOrb, Shared>>,
 
     // Bullet manager for ticking enemies
+    #[property]
+    bullet_manager_path: NodePath,
     bullet_manager: Option<TInstance<'static, BulletManager, Shared>>,
-    // Player for tracking position for collisions
-    player: Option<TInstance<'static, Player, Shared>>,
+    // Players for tracking position for collisions and aiming,
+    // looked up from the encounter manager
+    #[property]
+    encounter_manager_path: NodePath,
+    players: Option<Vec<TInstance<'static, Player, Shared>>>,
+
+    // Condition for the EncounterManager to start the encounter
+    // "previous_ended", "time" or "previous_killed"
+    #[property]
+    start_trigger: String,
+    // Time since the stage started (msec) for "time"
+    #[property(default = 0)]
+    start_time_ms: i64,
+    // Percentage of the previous encounter killed for "previous_killed"
+    #[property(default = 100.0)]
+    start_killed_percent: f32,
+    // Parsed from the properties above in _ready
+    start_trigger_type: StartTrigger,
 
-    // Time (secs) that the encounter will last before timing out
+    // Time (msec) that the encounter will last before timing out
     // -1 = infinite
     #[property(default = -1)]
     encounter_length: i64,
-    // Time the encounter started
-    encounter_starttime: i64,
+    // Simulation time since the encounter started (msec)
+    encounter_time: f32,
     // Time to wait after encounter completion (Handled by EncounterManager)
     #[property(default = 0)]
     encounter_end_delay: i64,
+    // Handling of enemy bullets once the encounter is over
+    // "immediate", "wait_for_clear" or "cancel_bullets"
+    #[property]
+    encounter_end_policy: String,
+    // Turn bullets into score items with "cancel_bullets"
+    #[property(default = false)]
+    encounter_cancel_to_items: bool,
+    // Parsed from the properties above in _ready
+    end_policy_type: EndPolicy,
     // Wether the encounter has completed
     ended: bool,
+    // Wether every enemy has moved into position
+    in_position: bool,
+
+    // Statistics for scoring, updated every tick
+    enemies_killed: u32,
+    enemies_escaped: u32,
+    enemies_total: u32,
 }
 
 #[methods]
 impl Encounter {
     fn new(_owner: &Node2D) -> Self {
         Self {
+            bullet_manager_path: NodePath::from_str("../../Bullets"),
+            encounter_manager_path: NodePath::from_str(".."),
             encounter_length: -1,
+            encounter_end_policy: "immediate".to_string(),
+            start_trigger: "previous_ended".to_string(),
+            start_killed_percent: 100.0,
             ..Default::default()
         }
     }
 
+    fn register(builder: &ClassBuilder<Self>) {
+        generic_encounter::register_signals(builder);
+    }
+
     // Adds refrences to each enemy in the encounter to
-    // their field in the struct
+    // their field in the struct.
+    // The group is optional, but every child in it must be a `class_name`
     fn process_children<T>(
         owner: &Node2D,
         name: &'static str,
+        class_name: &str,
         list: &mut Vec<TInstance<'static, T, Shared>>,
     ) where
         T: NativeClass,
         <T as NativeClass>::Base: SubClass<Node>,
     {
-        for child in unsafe { owner.get_node_as::<Node2D>(name).unwrap() }
-            .get_children()
-            .iter()
-        {
-            let child = unsafe { child.to_object::<Node2D>().unwrap().assume_safe() };
-            let instance = unsafe { child.get_node_as_instance::<T>(".").unwrap() };
-
-            list.push(instance);
+        let group = match unsafe { owner.get_node_as::<Node2D>(name) } {
+            Some(group) => group,
+            None => return,
+        };
+        for child in group.get_children().iter() {
+            let child = match child.to_object::<Node>() {
+                Some(child) => unsafe { child.assume_safe() },
+                None => continue,
+            };
+            if let Some(instance) =
+                node_paths::fetch_instance(&*child, &NodePath::from_str("."), class_name)
+            {
+                list.push(instance);
+            }
         }
     }
 
     #[export]
     fn _ready(&mut self, owner: &Node2D) {
         self.bullet_manager =
-            unsafe { owner.get_node_as_instance::<BulletManager>("../../Bullets") };
-        self.player = unsafe { owner.get_node_as_instance::<Player>("../../Player") };
+            node_paths::fetch_instance(owner, &self.bullet_manager_path, "BulletManager");
+        let manager =
+            node_paths::fetch_instance(owner, &self.encounter_manager_path, "EncounterManager");
+        self.players = encounter_manager::players(&manager);
+        node_paths::require(
+            owner,
+            self.bullet_manager.is_some() && self.players.is_some(),
+        );
+
+        // Parse once, so unknown names are only warned about here
+        self.start_trigger_type = StartTrigger::from_name(
+            &self.start_trigger,
+            self.start_time_ms,
+            self.start_killed_percent,
+        );
+        self.end_policy_type =
+            EndPolicy::from_name(&self.encounter_end_policy, self.encounter_cancel_to_items);
 
         // Populate the enemy list
-        Encounter::process_children(owner, "Orbs", &mut self.orbs);
-        Encounter::process_children(owner, "SmallOrbs", &mut self.small_orbs);
+        Encounter::process_children(owner, "Orbs", "Orb", &mut self.orbs);
+        Encounter::process_children(owner, "SmallOrbs", "SmallOrb", &mut self.small_orbs);
+    }
+
+    // Number of enemies killed by the player
+    #[export]
+    pub fn enemies_killed(&self, _owner: &Node2D) -> u32 {
+        self.enemies_killed
+    }
+
+    // Number of enemies that left the screen without being killed
+    #[export]
+    pub fn enemies_escaped(&self, _owner: &Node2D) -> u32 {
+        self.enemies_escaped
     }
 
     fn process_enemies<T>(
         items: &Vec<TInstance<'static, T, Shared>>,
         bullet_manager: &TInstance<'static, BulletManager, Shared>,
-        player_pos: Vector2,
+        players: &[TInstance<'static, Player, Shared>],
+        hitboxes: &[PlayerHitbox],
+        rank: RankScale,
         deltatime: f32,
-    ) -> usize
+    ) -> EnemyCounts
     where
         T: GenericEnemy + NativeClass,
         <T as NativeClass>::UserData: MapMut,
         Node2D: SubClass<<T as NativeClass>::Base>,
     {
-        let mut remaining_enemies = items.len();
+        let mut counts = EnemyCounts::default();
+        let positions: Vec<Vector2> = hitboxes.iter().map(|x| x.position).collect();
         for enemy in items {
             enemy
                 .map_mut(|x: &mut T, node: TRef<T::Base>| {
+                    if x.is_killed() {
+                        counts.killed += 1;
+                        return;
+                    } else if x.is_escaped() {
+                        counts.escaped += 1;
+                        return;
+                    }
+                    counts.remaining += 1;
+
+                    // Move the enemy along its path, enabling it once the path is complete
+                    let node2d = node.cast::<Node2D>().unwrap();
+                    let movement = x.movement_mut();
+                    let new_pos = movement.step(node2d.global_position(), deltatime);
+                    let arrived = movement.has_arrived();
+                    let escaped = movement.has_escaped(new_pos, T::HITBOX_SIZE);
+                    node2d.set_global_position(new_pos);
+
+                    // Retire enemies that have left the playfield
+                    if escaped {
+                        x.set_escaped(true);
+                        node2d.set_visible(false);
+                        return;
+                    }
+
+                    if !x.is_enabled() && arrived {
+                        x.set_enabled(true);
+                    }
+
+                    // Bodies collide with the players while moving into position too
+                    let body_radius = T::HITBOX_SIZE as f32;
+                    for i in touching_players(new_pos, body_radius, x.contact_damage(), hitboxes) {
+                        players[i]
+                            .map_mut(|x: &mut Player, node: TRef<Node2D>| x.hit(node.as_ref()))
+                            .unwrap();
+                    }
+
                     if x.is_enabled() {
-                        x.tick(node.as_ref(), bullet_manager, player_pos, deltatime)
-                    } else if x.is_killed() {
-                        remaining_enemies -= 1;
+                        let targets = Targets::new(&positions);
+                        x.tick(node.as_ref(), bullet_manager, targets, rank, deltatime)
                     } else {
-                        let node2d = node.cast::<Node2D>().unwrap();
-                        let pos = node2d.global_position();
-                        let goal = x.goal_position(node.as_ref());
-                        let movement_speed = 80.0;
-
-                        let angle = (-pos.y + goal.y).atan2(-pos.x + goal.x);
-                        let mut new_pos = Vector2::new(pos.x, pos.y)
-                            + (Vector2::new(angle.cos(), angle.sin()) * movement_speed * deltatime);
-                        if (new_pos.x - goal.x).powf(2.0) + (new_pos.y - goal.y).powf(2.0)
-                            <= 400.0 * deltatime
-                        {
-                            new_pos = goal;
-                            x.set_enabled(true);
-                        }
-
-                        node2d.set_global_position(new_pos);
+                        counts.entering += 1;
                     }
                 })
                 .unwrap();
         }
 
-        remaining_enemies
+        counts
     }
 
+    // Checks the path of a bullet from `from` to `to` against each enemy
     fn process_hits<T>(
         items: &Vec<TInstance<'static, T, Shared>>,
-        position: Vector2,
+        from: Vector2,
+        to: Vector2,
         radius: u32,
     ) -> bool
     where
@@ -139,11 +284,12 @@ impl Encounter {
             let pos = enemy
                 .map(|_, node: TRef<T::Base>| node.cast::<Node2D>().unwrap().global_position())
                 .unwrap();
-            if (pos.x - position.x).powf(2.0) + (pos.y - position.y).powf(2.0)
-                <= (radius as f32 + 5.0).powf(2.0)
-            {
+            if collision::segment_distance(pos, from, to) <= radius as f32 + 5.0 {
                 if enemy
-                    .map_mut(|x: &mut T, node: TRef<T::Base>| x.hit(node.as_ref()))
+                    .map_mut(|x: &mut T, node: TRef<T::Base>| {
+                        // Escaped enemies can no longer be damaged
+                        !x.is_escaped() && x.hit(node.as_ref())
+                    })
                     .unwrap()
                 {
                     return true;
@@ -153,55 +299,304 @@ impl Encounter {
 
         false
     }
+
+    // Positions of the enemies that are enabled and can still be hit
+    fn positions_of<T>(items: &Vec<TInstance<'static, T, Shared>>) -> Vec<Vector2>
+    where
+        T: NativeClass + GenericEnemy,
+        <T as NativeClass>::UserData: Map,
+        Node2D: SubClass<<T as NativeClass>::Base>,
+    {
+        items
+            .iter()
+            .filter_map(|enemy| {
+                enemy
+                    .map(|x: &T, node: TRef<T::Base>| {
+                        if x.is_enabled() && !x.is_killed() && !x.is_escaped() {
+                            Some(node.cast::<Node2D>().unwrap().global_position())
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn set_enemy_difficulty<T>(
+        items: &Vec<TInstance<'static, T, Shared>>,
+        profile: DifficultyProfile,
+    ) where
+        T: NativeClass + GenericEnemy,
+        <T as NativeClass>::UserData: MapMut,
+    {
+        for enemy in items {
+            enemy
+                .map_mut(|x: &mut T, _node: TRef<T::Base>| x.set_difficulty(profile))
+                .unwrap();
+        }
+    }
+
+    fn snapshot_enemies<T>(items: &Vec<TInstance<'static, T, Shared>>) -> Vec<EnemyNodeSnapshot>
+    where
+        T: NativeClass + GenericEnemy,
+        <T as NativeClass>::UserData: Map,
+        Node2D: SubClass<<T as NativeClass>::Base>,
+    {
+        items
+            .iter()
+            .map(|enemy| {
+                enemy
+                    .map(|x: &T, node: TRef<T::Base>| {
+                        let node2d = node.cast::<Node2D>().unwrap();
+                        EnemyNodeSnapshot {
+                            position: save_state::to_position(node2d.global_position()),
+                            visible: node2d.is_visible(),
+                            enemy: x.snapshot(),
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn restore_enemies<T>(
+        items: &Vec<TInstance<'static, T, Shared>>,
+        snapshots: &[EnemyNodeSnapshot],
+    ) where
+        T: NativeClass + GenericEnemy,
+        <T as NativeClass>::UserData: MapMut,
+        Node2D: SubClass<<T as NativeClass>::Base>,
+    {
+        for (enemy, snapshot) in items.iter().zip(snapshots) {
+            enemy
+                .map_mut(|x: &mut T, node: TRef<T::Base>| {
+                    let node2d = node.cast::<Node2D>().unwrap();
+                    node2d.set_global_position(save_state::from_position(snapshot.position));
+                    node2d.set_visible(snapshot.visible);
+                    x.restore(&snapshot.enemy);
+                })
+                .unwrap();
+        }
+    }
 }
 
 impl GenericEncounter for Encounter {
-    fn activate(&mut self, owner: &Node2D) {
+    fn on_pending(&mut self, owner: &Node2D) {
+        owner.set_visible(false);
+    }
+    fn on_entering(&mut self, owner: &Node2D) {
         owner.set_visible(true);
-        self.encounter_starttime = OS::godot_singleton().get_ticks_msec();
+        self.encounter_time = 0.0;
+        signals::emit_deferred(owner, "encounter_started", &[]);
     }
-    fn deactivate(&mut self, owner: &Node2D) {
+    fn on_active(&mut self, _owner: &Node2D) {}
+    fn on_ending(&mut self, _owner: &Node2D) {}
+    fn on_finished(&mut self, owner: &Node2D) {
         owner.set_visible(false);
+        signals::emit_deferred(owner, "encounter_ended", &[]);
     }
 
+    fn in_position(&self) -> bool {
+        self.in_position
+    }
     fn has_ended(&self) -> bool {
         self.ended
     }
     fn end_delay(&self) -> i64 {
         self.encounter_end_delay
     }
+    fn end_policy(&self) -> EndPolicy {
+        self.end_policy_type
+    }
+    fn start_trigger(&self) -> StartTrigger {
+        self.start_trigger_type
+    }
+    fn killed_fraction(&self) -> f32 {
+        if self.enemies_total == 0 {
+            0.0
+        } else {
+            self.enemies_killed as f32 / self.enemies_total as f32
+        }
+    }
+    fn boss_status(&self) -> Option<BossStatus> {
+        None
+    }
+
+    fn tick(&mut self, _owner: &Node2D, rank: RankScale, deltatime: f32) {
+        let (players, bullet_manager) = match (&self.players, &self.bullet_manager) {
+            (Some(players), Some(bullet_manager)) => (players, bullet_manager),
+            // Without its nodes the encounter can't run, end it rather than stall the stage
+            _ => {
+                self.ended = true;
+                return;
+            }
+        };
+
+        // Enemies neither touch nor aim at dead players
+        let (players, hitboxes) = player::live_players(players);
 
-    fn tick(&mut self, _owner: &Node2D, deltatime: f32) {
-        let player_pos = self
-            .player
-            .as_ref()
-            .unwrap()
-            .map(|_x, node| node.get_global_transform().origin)
-            .unwrap();
-
-        let bullet_manager = self.bullet_manager.as_ref().unwrap();
-
-        let remaining_enemies =
-            Encounter::process_enemies(&self.orbs, bullet_manager, player_pos, deltatime)
-                + Encounter::process_enemies(
-                    &self.small_orbs,
-                    bullet_manager,
-                    player_pos,
-                    deltatime,
-                );
-
-        // TODO: Additionally wait for non-player bullets to be destroyed, allowing for a clear playspace
-        if remaining_enemies == 0 {
-            self.ended = true;
-        } else if self.encounter_length != -1 && OS::godot_singleton().get_ticks_msec() - self.encounter_starttime
-            >= self.encounter_length
+        let counts = Encounter::process_enemies(
+            &self.orbs,
+            bullet_manager,
+            &players,
+            &hitboxes,
+            rank,
+            deltatime,
+        ) + Encounter::process_enemies(
+            &self.small_orbs,
+            bullet_manager,
+            &players,
+            &hitboxes,
+            rank,
+            deltatime,
+        );
+        self.enemies_killed = counts.killed as u32;
+        self.enemies_escaped = counts.escaped as u32;
+        self.enemies_total = (counts.remaining + counts.killed + counts.escaped) as u32;
+
+        // Finish once every enemy has been killed or has escaped, or on timeout,
+        // then end according to the policy for the remaining bullets
+        self.encounter_time += deltatime * 1000.0;
+        self.in_position = counts.entering == 0;
+        let timed_out =
+            self.encounter_length != -1 && self.encounter_time >= self.encounter_length as f32;
+        if !self.ended && (counts.cleared() || timed_out) {
+            self.ended = self.end_policy().apply(bullet_manager);
+        }
+    }
+
+    fn hit_enemy(&mut self, _owner: &Node2D, from: Vector2, to: Vector2, radius: u32) -> bool {
+        return Encounter::process_hits(&self.orbs, from, to, radius)
+            || Encounter::process_hits(&self.small_orbs, from, to, radius);
+    }
+
+    fn enemy_positions(&self, _owner: &Node2D) -> Vec<Vector2> {
+        let mut positions = Encounter::positions_of(&self.orbs);
+        positions.extend(Encounter::positions_of(&self.small_orbs));
+        positions
+    }
+
+    fn set_difficulty(&mut self, _owner: &Node2D, profile: DifficultyProfile) {
+        Encounter::set_enemy_difficulty(&self.orbs, profile);
+        Encounter::set_enemy_difficulty(&self.small_orbs, profile);
+    }
+
+    fn skip_to_phase(&mut self, _owner: &Node2D, phase: u32) -> bool {
+        // Regular encounters only have a single phase
+        phase == 0
+    }
+
+    fn snapshot(&self, _owner: &Node2D) -> EncounterSnapshot {
+        EncounterSnapshot::GenericEncounter(GenericEncounterSnapshot {
+            encounter_time: self.encounter_time,
+            ended: self.ended,
+            in_position: self.in_position,
+            enemies_killed: self.enemies_killed,
+            enemies_escaped: self.enemies_escaped,
+            enemies_total: self.enemies_total,
+            orbs: Encounter::snapshot_enemies(&self.orbs),
+            small_orbs: Encounter::snapshot_enemies(&self.small_orbs),
+        })
+    }
+
+    fn restore(
+        &mut self,
+        owner: &Node2D,
+        snapshot: &EncounterSnapshot,
+    ) -> Result<(), SaveStateError> {
+        let snapshot = match snapshot {
+            EncounterSnapshot::GenericEncounter(snapshot) => snapshot,
+            _ => return Err(SaveStateError::Mismatch("Expected an Encounter".to_string())),
+        };
+        if snapshot.orbs.len() != self.orbs.len()
+            || snapshot.small_orbs.len() != self.small_orbs.len()
         {
-            self.ended = true;
+            return Err(SaveStateError::Mismatch(format!(
+                "Enemies in {} have changed",
+                owner.name()
+            )));
         }
+
+        self.encounter_time = snapshot.encounter_time;
+        self.ended = snapshot.ended;
+        self.in_position = snapshot.in_position;
+        self.enemies_killed = snapshot.enemies_killed;
+        self.enemies_escaped = snapshot.enemies_escaped;
+        self.enemies_total = snapshot.enemies_total;
+        Encounter::restore_enemies(&self.orbs, &snapshot.orbs);
+        Encounter::restore_enemies(&self.small_orbs, &snapshot.small_orbs);
+        Ok(())
     }
+}
 
-    fn hit_enemy(&mut self, _owner: &Node2D, pos: Vector2, radius: u32) -> bool {
-        return Encounter::process_hits(&self.orbs, pos, radius)
-            || Encounter::process_hits(&self.small_orbs, pos, radius);
+// Players whose hitbox overlaps the body of an enemy at `position`,
+// always none when its body is harmless
+fn touching_players(
+    position: Vector2,
+    body_radius: f32,
+    contact_damage: bool,
+    hitboxes: &[PlayerHitbox],
+) -> Vec<usize> {
+    if !contact_damage {
+        return vec![];
     }
-}
\ No newline at end of file
+    hitboxes
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| position.distance_to(x.position) <= body_radius + x.radius)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_enemies_count_towards_clearing() {
+        let escaped = EnemyCounts {
+            escaped: 1,
+            ..Default::default()
+        };
+        let killed = EnemyCounts {
+            killed: 2,
+            ..Default::default()
+        };
+        assert!((escaped + killed).cleared());
+
+        // An enemy that never leaves keeps the encounter going
+        let staying = EnemyCounts {
+            remaining: 1,
+            ..Default::default()
+        };
+        assert!(!(escaped + killed + staying).cleared());
+    }
+
+    fn hitbox(x: f32, y: f32) -> PlayerHitbox {
+        PlayerHitbox {
+            position: Vector2::new(x, y),
+            radius: 2.0,
+        }
+    }
+
+    #[test]
+    fn damaging_bodies_hit_overlapping_players() {
+        let enemy = Vector2::new(100.0, 100.0);
+        // Overlapping, exactly touching and just apart from a 9px body
+        let hitboxes = [
+            hitbox(100.0, 100.0),
+            hitbox(111.0, 100.0),
+            hitbox(100.0, 111.5),
+        ];
+        assert_eq!(touching_players(enemy, 9.0, true, &hitboxes), vec![0, 1]);
+        assert_eq!(touching_players(enemy, 9.0, true, &[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn harmless_bodies_hit_nobody() {
+        let enemy = Vector2::new(100.0, 100.0);
+        let hitboxes = [hitbox(100.0, 100.0)];
+        assert!(touching_players(enemy, 9.0, false, &hitboxes).is_empty());
+    }
+}
@@ -1,11 +1,19 @@
 use gdnative::api::Node2D;
-use gdnative::api::OS;
 use gdnative::export::user_data::LocalCellError;
 use gdnative::prelude::*;
 
+use crate::bullet_manager::BulletManager;
 use crate::custom_encounter::first_boss::FirstBoss;
-use crate::custom_encounter::generic_encounter::GenericEncounter;
+use crate::difficulty::Difficulty;
+use crate::custom_encounter::generic_encounter::{BossStatus, GenericEncounter, StartTrigger};
+use crate::custom_encounter::lifecycle::{EncounterState, Lifecycle};
 use crate::encounter::Encounter;
+use crate::node_paths;
+use crate::player::{self, Player};
+use crate::rank::{self, Rank};
+use crate::save_state::{EncounterManagerSnapshot, EncounterSnapshot, SaveStateError};
+use crate::signals;
+use crate::time_scale::{self, TimeScale};
 
 pub enum EncounterType {
     GenericEncounter(TInstance<'static, Encounter, Shared>),
@@ -26,101 +34,572 @@ impl EncounterType {
             }
         }
     }
+
+    // Name of the encounter's node, used to identify it in signals
+    fn name(&self) -> GodotString {
+        self.map_mut(|_encounter: &mut dyn GenericEncounter, node: &Node2D| node.name())
+            .unwrap()
+    }
+}
+
+// Runs the lifecycle hook for the state an encounter has just entered
+fn run_hook(encounter: &mut dyn GenericEncounter, node: &Node2D, state: EncounterState) {
+    match state {
+        EncounterState::Pending => encounter.on_pending(node),
+        EncounterState::Entering => encounter.on_entering(node),
+        EncounterState::Active => encounter.on_active(node),
+        EncounterState::Ending => encounter.on_ending(node),
+        EncounterState::Finished => encounter.on_finished(node),
+    }
+}
+
+// Players set on the encounter manager, for the nodes that need them.
+// None when it or any of the players is missing, the error having been reported
+pub fn players(
+    encounter_manager: &Option<TInstance<'static, EncounterManager, Shared>>,
+) -> Option<Vec<TInstance<'static, Player, Shared>>> {
+    encounter_manager
+        .as_ref()?
+        .map(|x: &EncounterManager, node: TRef<Node2D>| x.players(&node))
+        .unwrap()
 }
 
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct EncounterManager {
     // List of encounters to progress through
     encounters: Vec<EncounterType>,
-    // The index for the currently active encounter
-    active_encounter: usize,
-    // Time when the encounter ended (for handling end delay)
-    // -1 for not yet set
-    encounter_end: i64,
+    // Lifecycle of each encounter, several may be running at once
+    lifecycles: Vec<Lifecycle>,
+    // Simulation time since the stage started (msec), used by StartTrigger::Time
+    stage_time: f32,
+    // Wether every encounter has finished
+    stage_cleared: bool,
+
+    // Time scale applied to every encounter
+    #[property]
+    time_scale_path: NodePath,
+    time_scale: Option<TInstance<'static, TimeScale, Shared>>,
+    // Length of the freeze when a boss is defeated (msec)
+    #[property(default = 300)]
+    boss_hit_stop_ms: i64,
+
+    // Dynamic rank applied to enemy patterns
+    #[property]
+    rank_path: NodePath,
+    rank: Option<TInstance<'static, Rank, Shared>>,
+
+    // Difficulty of the run, "easy", "normal", "hard" or "lunatic"
+    // Only read when the stage starts
+    #[property]
+    difficulty: String,
+    difficulty_level: Difficulty,
+
+    // Practice mode, starts the stage from a later encounter
+    // The name is used instead of the index when set
+    #[property(default = 0)]
+    start_encounter: i64,
+    #[property]
+    start_encounter_name: String,
+    // Boss phase to start the encounter from
+    #[property(default = 0)]
+    start_phase: u32,
+    // Bullets are cleared when jumping between encounters
+    #[property]
+    bullet_manager_path: NodePath,
+    bullet_manager: Option<TInstance<'static, BulletManager, Shared>>,
+    // The players are restarted when jumping between encounters.
+    // Other nodes look them up from here, see `players`
+    #[property]
+    player_path: NodePath,
+    // Empty for single player
+    #[property]
+    player_2_path: NodePath,
+    players: Vec<TInstance<'static, Player, Shared>>,
+    // State of each encounter at the start of the stage, restored when jumping
+    initial_state: Vec<EncounterSnapshot>,
+    // Phase to skip to once the encounter at the index starts
+    pending_phase: Option<(usize, u32)>,
 }
 
 #[methods]
 impl EncounterManager {
     fn new(_owner: &Node2D) -> Self {
-        Self::default()
+        Self {
+            time_scale_path: NodePath::from_str("../Time"),
+            boss_hit_stop_ms: 300,
+            rank_path: NodePath::from_str("../Rank"),
+            difficulty: "normal".to_string(),
+            bullet_manager_path: NodePath::from_str("../Bullets"),
+            player_path: NodePath::from_str("../Player"),
+            ..Default::default()
+        }
+    }
+
+    // Signals for following the progress of the stage
+    // - encounter_started(index, name): Pending -> Entering
+    // - encounter_ended(index, name): Ending -> Finished
+    // - stage_cleared: Every encounter has finished
+    fn register(builder: &ClassBuilder<Self>) {
+        builder
+            .signal("encounter_started")
+            .with_param("index", VariantType::I64)
+            .with_param("name", VariantType::GodotString)
+            .done();
+        builder
+            .signal("encounter_ended")
+            .with_param("index", VariantType::I64)
+            .with_param("name", VariantType::GodotString)
+            .done();
+        builder.signal("stage_cleared").done();
     }
 
     #[export]
     fn _ready(&mut self, owner: &Node2D) {
+        self.time_scale = node_paths::fetch_instance(owner, &self.time_scale_path, "TimeScale");
+        self.bullet_manager =
+            node_paths::fetch_instance(owner, &self.bullet_manager_path, "BulletManager");
+        self.rank = node_paths::fetch_instance(owner, &self.rank_path, "Rank");
+
         // Store a refrence to every Encounter stored in the children of Encounters
         for child in owner.get_children().iter() {
-            let child = unsafe { child.to_object::<Node2D>().unwrap().assume_safe() };
+            let child = match child.to_object::<Node>() {
+                Some(child) => unsafe { child.assume_safe() },
+                None => continue,
+            };
             let instance =
                 unsafe {child.get_node_as_instance::<Encounter>(".").map(|x| EncounterType::GenericEncounter(x))}
-                .or(unsafe {child.get_node_as_instance::<FirstBoss>(".").map(|x| EncounterType::FirstBoss(x))})
-                .unwrap();
+                .or(unsafe {child.get_node_as_instance::<FirstBoss>(".").map(|x| EncounterType::FirstBoss(x))});
             // TODO: Use `.or()` to implement for additional encounter types
+            let instance = match instance {
+                Some(instance) => instance,
+                None => {
+                    godot_error!(
+                        "{}: Child {} is not an Encounter or FirstBoss, skipping",
+                        owner.name(),
+                        child.name()
+                    );
+                    continue;
+                }
+            };
 
             self.encounters.push(instance);
+            self.lifecycles.push(Lifecycle::default());
         }
 
-        // Tell each encounter if it is active or inactive
-        for i in 0..self.encounters.len() {
-            if i == 0 {
-                self.encounters[i]
-                    .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
-                        encounter.activate(node);
-                    }).unwrap();
-            } else {
-                self.encounters[i]
-                    .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
-                        encounter.deactivate(node);
-                    }).unwrap();
+        self.difficulty_level = Difficulty::from_name(&self.difficulty).unwrap_or_else(|| {
+            godot_error!("{}: Unknown difficulty {}, using normal", owner.name(), self.difficulty);
+            Difficulty::Normal
+        });
+        let profile = self.difficulty_level.profile();
+
+        // Every encounter starts Pending, and is started by its StartTrigger
+        for encounter in &self.encounters {
+            encounter
+                .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                    encounter.set_difficulty(node, profile);
+                    encounter.on_pending(node);
+                }).unwrap();
+        }
+        self.initial_state = self.snapshot().encounters;
+
+        let start = if self.start_encounter_name.is_empty() {
+            Some(self.start_encounter.max(0) as usize)
+        } else {
+            self.find_encounter(&self.start_encounter_name)
+        };
+        match start {
+            Some(0) if self.start_phase == 0 => {}
+            Some(index) => {
+                self.jump_to(index, self.start_phase);
             }
+            None => godot_error!(
+                "{}: No encounter named {}, starting from the first",
+                owner.name(),
+                self.start_encounter_name
+            ),
         }
+        // Fetched after the start jump, the players aren't ready yet and start fresh anyway
+        self.players = self.players(owner).unwrap_or_default();
     }
 
-    // Tick the current encounter and check if ready to progress
+    // Start any encounters whose trigger has fired, then
+    // tick and advance the lifecycle of the running encounters
     #[export]
-    fn _process(&mut self, _owner: &Node2D, deltatime: f32) {
-        if self.encounters.len() > self.active_encounter {
-            self.encounters[self.active_encounter]
+    fn _process(&mut self, owner: &Node2D, deltatime: f32) {
+        // Nothing progresses while paused or in a hit-stop
+        let deltatime = time_scale::scaled(&self.time_scale, deltatime);
+        if deltatime == 0.0 {
+            return;
+        }
+        self.stage_time += deltatime * 1000.0;
+        let rank = rank::scale(&self.rank);
+
+        for i in 0..self.encounters.len() {
+            if self.lifecycles[i].state() != EncounterState::Pending {
+                continue;
+            }
+            if !self.should_start(i) {
+                // Encounters start in order
+                break;
+            }
+
+            self.lifecycles[i].start();
+            let phase = match self.pending_phase {
+                Some((index, phase)) if index == i => {
+                    self.pending_phase = None;
+                    Some(phase)
+                }
+                _ => None,
+            };
+            self.encounters[i]
                 .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
-                    if encounter.has_ended() {
-                        if OS::godot_singleton().get_ticks_msec() - encounter.end_delay() >= self.encounter_end {
-                            encounter.activate(node);
-                            self.active_encounter += 1;
-                            self.encounter_end = -1;
-                            if self.encounters.len() > self.active_encounter {
-                                self.encounters[self.active_encounter]
-                                    .map_mut(| encounter: &mut dyn GenericEncounter, node: &Node2D| {
-                                        encounter.activate(node);
-                                    })
-                                    .unwrap();
-                            }
-                        } else {
-                            if self.encounter_end == -1 {
-                                self.encounter_end = OS::godot_singleton().get_ticks_msec();
-                                encounter.tick(node, deltatime);
-                            }
+                    run_hook(encounter, node, EncounterState::Entering);
+                    if let Some(phase) = phase {
+                        if !encounter.skip_to_phase(node, phase) {
+                            godot_warn!("{} has no phase {phase}", node.name());
                         }
-                    } else {
-                        encounter.tick(node, deltatime);
                     }
                 })
                 .unwrap();
-        } else {
+            signals::emit_deferred(
+                owner,
+                "encounter_started",
+                &[(i as i64).to_variant(), self.encounters[i].name().to_variant()],
+            );
+        }
+
+        for i in 0..self.encounters.len() {
+            let lifecycle = &mut self.lifecycles[i];
+            if !lifecycle.is_running() {
+                continue;
+            }
+
+            let (transition, is_boss) = self.encounters[i]
+                .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                    encounter.tick(node, rank, deltatime);
+
+                    let transition = lifecycle.update(
+                        deltatime,
+                        encounter.in_position(),
+                        encounter.has_ended(),
+                        encounter.end_delay(),
+                    );
+                    if let Some(state) = transition {
+                        run_hook(encounter, node, state);
+                    }
+                    (transition, encounter.boss_status().is_some())
+                })
+                .unwrap();
+
+            // Freeze briefly on defeating a boss
+            if transition == Some(EncounterState::Ending) && is_boss {
+                if let Some(time_scale) = &self.time_scale {
+                    time_scale
+                        .map_mut(|x: &mut TimeScale, node: TRef<Node>| {
+                            x.hit_stop(node.as_ref(), self.boss_hit_stop_ms)
+                        })
+                        .unwrap();
+                }
+            }
+
+            if transition == Some(EncounterState::Finished) {
+                signals::emit_deferred(
+                    owner,
+                    "encounter_ended",
+                    &[(i as i64).to_variant(), self.encounters[i].name().to_variant()],
+                );
+            }
+        }
+
+        if !self.stage_cleared
+            && self
+                .lifecycles
+                .iter()
+                .all(|x| x.state() == EncounterState::Finished)
+        {
+            self.stage_cleared = true;
             // TODO: Link with some sort of Stage manger
             godot_warn!("No more encounters!");
+            signals::emit_deferred(owner, "stage_cleared", &[]);
         }
     }
 
-    // Forward calls to the current encounter
-    pub fn hit_enemy(&mut self, _owner: &Node2D, position: Vector2, radius: u32) -> bool {
-        if self.encounters.len() > self.active_encounter {
-            self.encounters[self.active_encounter]
+    // Practice mode, restarts the stage from the encounter at `index`.
+    // Earlier encounters are skipped, later ones are put back to how they were at
+    // the start of the stage, every bullet is cleared and the players restart with
+    // their loadouts. The encounter then starts on the next tick, from the given boss phase.
+    // Must not be called from encounter signal handlers, as those run during the tick
+    pub fn jump_to(&mut self, index: usize, phase: u32) -> bool {
+        if index >= self.encounters.len() {
+            godot_error!(
+                "Can't jump to encounter {index}, there are only {}",
+                self.encounters.len()
+            );
+            return false;
+        }
+
+        for (i, (encounter, snapshot)) in
+            self.encounters.iter().zip(&self.initial_state).enumerate()
+        {
+            encounter
                 .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
-                    encounter.hit_enemy(node, position, radius)
+                    // The snapshot was taken from this encounter, so it always matches
+                    encounter.restore(node, snapshot).unwrap();
+                    if i < index {
+                        node.set_visible(false);
+                    } else {
+                        encounter.on_pending(node);
+                    }
+                })
+                .unwrap();
+
+            if i < index {
+                self.lifecycles[i].skip();
+            } else {
+                self.lifecycles[i].reset();
+            }
+        }
+
+        // Start straight away, even for encounters started by time
+        let trigger = self.encounters[index]
+            .map_mut(|encounter: &mut dyn GenericEncounter, _node: &Node2D| {
+                encounter.start_trigger()
+            })
+            .unwrap();
+        self.stage_time = match trigger {
+            StartTrigger::Time { ms } => ms as f32,
+            _ => 0.0,
+        };
+        self.stage_cleared = false;
+        self.pending_phase = Some((index, phase));
+
+        if let Some(bullet_manager) = &self.bullet_manager {
+            bullet_manager
+                .map_mut(|x: &mut BulletManager, _node: TRef<Node2D>| x.clear())
+                .unwrap();
+        }
+        for player in &self.players {
+            player
+                .map_mut(|x: &mut Player, node: TRef<Node2D>| x.restart(node.as_ref()))
+                .unwrap();
+        }
+        true
+    }
+
+    // Fetches the players from the paths set on the encounter manager, which every
+    // other node goes through so they only have to be set once.
+    // None when any of them is missing, the error having been reported
+    pub fn players(&self, owner: &Node) -> Option<Vec<TInstance<'static, Player, Shared>>> {
+        player::fetch_players(owner, &self.player_path, &self.player_2_path)
+    }
+
+    #[export]
+    pub fn jump_to_encounter(&mut self, _owner: &Node2D, index: i64, phase: u32) -> bool {
+        self.jump_to(index.max(0) as usize, phase)
+    }
+
+    // Same as `jump_to_encounter`, using the name of the encounter's node
+    #[export]
+    pub fn jump_to_encounter_name(&mut self, _owner: &Node2D, name: String, phase: u32) -> bool {
+        match self.find_encounter(&name) {
+            Some(index) => self.jump_to(index, phase),
+            None => {
+                godot_error!("No encounter named {name}");
+                false
+            }
+        }
+    }
+
+    fn find_encounter(&self, name: &str) -> Option<usize> {
+        self.encounters
+            .iter()
+            .position(|encounter| encounter.name().to_string() == name)
+    }
+
+    // Checks the StartTrigger of an encounter against the previous encounter
+    fn should_start(&self, index: usize) -> bool {
+        if index > 0 && self.lifecycles[index - 1].state() == EncounterState::Pending {
+            return false;
+        }
+
+        let trigger = self.encounters[index]
+            .map_mut(|encounter: &mut dyn GenericEncounter, _node: &Node2D| {
+                encounter.start_trigger()
+            })
+            .unwrap();
+        match trigger {
+            StartTrigger::Time { ms } => self.stage_time >= ms as f32,
+            _ if index == 0 => true,
+            StartTrigger::PreviousEnded => {
+                self.lifecycles[index - 1].state() == EncounterState::Finished
+            }
+            StartTrigger::PreviousKilled { percent } => {
+                self.lifecycles[index - 1].state() == EncounterState::Finished
+                    || self.encounters[index - 1]
+                        .map_mut(|encounter: &mut dyn GenericEncounter, _node: &Node2D| {
+                            encounter.killed_fraction() * 100.0 >= percent
+                        })
+                        .unwrap()
+            }
+        }
+    }
+
+    // Status of the first running boss encounter, if any
+    pub fn boss_status(&self) -> Option<BossStatus> {
+        self.encounters
+            .iter()
+            .zip(&self.lifecycles)
+            .filter(|(_, lifecycle)| lifecycle.is_running())
+            .find_map(|(encounter, _)| {
+                encounter
+                    .map_mut(|encounter: &mut dyn GenericEncounter, _node: &Node2D| {
+                        encounter.boss_status()
+                    })
+                    .unwrap()
+            })
+    }
+
+    // Positions of every enemy in the running encounters
+    pub fn enemy_positions(&self) -> Vec<Vector2> {
+        self.encounters
+            .iter()
+            .zip(&self.lifecycles)
+            .filter(|(_, lifecycle)| lifecycle.is_running())
+            .flat_map(|(encounter, _)| {
+                encounter
+                    .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                        encounter.enemy_positions(node)
+                    })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    // Enemy closest to `point`, if any are in play
+    pub fn nearest_enemy(&self, point: Vector2) -> Option<Vector2> {
+        self.enemy_positions().into_iter().fold(None, |nearest, x| match nearest {
+            Some(nearest) if nearest.distance_to(point) <= x.distance_to(point) => Some(nearest),
+            _ => Some(x),
+        })
+    }
+
+    #[export]
+    pub fn nearest_enemy_position(&self, _owner: &Node2D, x: f32, y: f32) -> Option<Vector2> {
+        self.nearest_enemy(Vector2::new(x, y))
+    }
+
+    // Difficulty the stage was started with
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty_level
+    }
+
+    // Wether every encounter has finished
+    pub fn is_stage_cleared(&self) -> bool {
+        self.stage_cleared
+    }
+
+    pub fn snapshot(&self) -> EncounterManagerSnapshot {
+        EncounterManagerSnapshot {
+            difficulty: self.difficulty_level,
+            stage_time: self.stage_time,
+            stage_cleared: self.stage_cleared,
+            lifecycles: self.lifecycles.iter().map(|x| x.snapshot()).collect(),
+            encounters: self
+                .encounters
+                .iter()
+                .map(|encounter| {
+                    encounter
+                        .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                            encounter.snapshot(node)
+                        })
+                        .unwrap()
+                })
+                .collect(),
+        }
+    }
+
+    // Restores every encounter, leaving the stage unchanged on failure
+    pub fn restore(&mut self, snapshot: &EncounterManagerSnapshot) -> Result<(), SaveStateError> {
+        if snapshot.difficulty != self.difficulty_level {
+            return Err(SaveStateError::Mismatch(format!(
+                "Saved on {}, playing on {}",
+                snapshot.difficulty.name(),
+                self.difficulty_level.name()
+            )));
+        }
+        if snapshot.encounters.len() != self.encounters.len()
+            || snapshot.lifecycles.len() != self.lifecycles.len()
+        {
+            return Err(SaveStateError::Mismatch(format!(
+                "Expected {} encounters, found {}",
+                self.encounters.len(),
+                snapshot.encounters.len()
+            )));
+        }
+
+        // Encounters check their own snapshot, so undo the ones
+        // already restored if a later one doesn't match
+        let previous = self.snapshot();
+        if let Err(error) = self.restore_encounters(snapshot) {
+            self.restore_encounters(&previous).unwrap();
+            return Err(error);
+        }
+
+        self.stage_time = snapshot.stage_time;
+        self.stage_cleared = snapshot.stage_cleared;
+        for (lifecycle, lifecycle_snapshot) in self.lifecycles.iter_mut().zip(&snapshot.lifecycles) {
+            lifecycle.restore(lifecycle_snapshot);
+        }
+        // Only running encounters are shown, as done by the lifecycle hooks
+        for (encounter, lifecycle) in self.encounters.iter().zip(&self.lifecycles) {
+            encounter
+                .map_mut(|_encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                    node.set_visible(lifecycle.is_running())
+                })
+                .unwrap();
+        }
+        Ok(())
+    }
+
+    fn restore_encounters(
+        &mut self,
+        snapshot: &EncounterManagerSnapshot,
+    ) -> Result<(), SaveStateError> {
+        for (encounter, encounter_snapshot) in self.encounters.iter().zip(&snapshot.encounters) {
+            encounter
+                .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                    encounter.restore(node, encounter_snapshot)
+                })
+                .unwrap()?;
+        }
+        Ok(())
+    }
+
+    // Forward calls to every running encounter
+    pub fn hit_enemy(
+        &mut self,
+        _owner: &Node2D,
+        from: Vector2,
+        to: Vector2,
+        radius: u32,
+    ) -> bool {
+        for i in 0..self.encounters.len() {
+            if !self.lifecycles[i].is_running() {
+                continue;
+            }
+
+            if self.encounters[i]
+                .map_mut(|encounter: &mut dyn GenericEncounter, node: &Node2D| {
+                    encounter.hit_enemy(node, from, to, radius)
                 })
                 .unwrap()
-        } else {
-            false
+            {
+                return true;
+            }
         }
+
+        false
     }
 }
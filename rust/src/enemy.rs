@@ -1,6 +1,7 @@
 use gdnative::prelude::InitHandle;
 
 pub mod generic_enemy;
+pub mod movement;
 
 pub mod orb;
 pub mod small_orb;
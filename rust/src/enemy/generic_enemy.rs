@@ -1,18 +1,64 @@
 use gdnative::prelude::*;
 
 use crate::bullet_manager::BulletManager;
+use crate::bullet_motion;
+use crate::difficulty::{DifficultyOverrides, DifficultyProfile};
+use crate::rank::RankScale;
+use crate::enemy::movement::Movement;
+use crate::save_state::EnemySnapshot;
+
+// Signals emitted by every enemy, registered from each enemy's `register`
+// - damaged(health): Hit by a player bullet
+// - killed: Health reached 0
+pub fn register_signals<T: NativeClass>(builder: &ClassBuilder<T>) {
+    builder
+        .signal("damaged")
+        .with_param("health", VariantType::I64)
+        .done();
+    builder.signal("killed").done();
+}
+
+// Positions of the players an enemy can aim at, player 1 first
+#[derive(Clone, Copy)]
+pub struct Targets<'a> {
+    players: &'a [Vector2],
+}
+
+impl<'a> Targets<'a> {
+    pub fn new(players: &'a [Vector2]) -> Self {
+        Self { players }
+    }
+
+    // Player closest to `from`
+    pub fn nearest(&self, from: Vector2) -> Option<Vector2> {
+        bullet_motion::nearest(self.players.iter().copied(), from)
+    }
+
+    // Player `index` (0 for player 1), or the nearest when
+    // `index` is -1 or that player isn't playing
+    pub fn choose(&self, index: i64, from: Vector2) -> Option<Vector2> {
+        let chosen = usize::try_from(index).ok().and_then(|x| self.players.get(x));
+        match chosen {
+            Some(pos) => Some(*pos),
+            None => self.nearest(from),
+        }
+    }
+}
 
 pub trait GenericEnemy: NativeClass {
-    // Used to determine if a Player's bullet has hit
+    // Used to determine if a Player's bullet, or the Player, has hit
     const HITBOX_SIZE: u32;
+    // Health on Normal, scaled by the difficulty
+    const BASE_HEALTH: u32;
 
     // Function called for the enemy to perform its actions
-    // as well as spawn bullets
+    // as well as spawn bullets. The rank scales its bullet speed and fire rate
     fn tick(
         &mut self,
         owner: &Self::Base,
         bullet_handler: &TInstance<'static, BulletManager, Shared>,
-        player_pos: Vector2,
+        targets: Targets,
+        rank: RankScale,
         deltatime: f32,
     );
 
@@ -36,7 +82,75 @@ pub trait GenericEnemy: NativeClass {
     // Used in determining if the encounter is ready to end.
     fn is_killed(&self) -> bool;
 
-    // Returns the position the EncounterManager moves the
-    // enemy towards before enabling.
-    fn goal_position(&self, owner: &Self::Base) -> Vector2;
+    // Tells the EncounterManager if the enemy left the screen
+    // without being killed. Escaped enemies count towards
+    // the encounter ending, but not towards kills.
+    fn is_escaped(&self) -> bool;
+    // Used to mark the enemy as escaped by the Encounter
+    fn set_escaped(&mut self, escaped: bool);
+
+    // Wether touching the enemy's body hits the player
+    fn contact_damage(&self) -> bool;
+
+    // Path the Encounter moves the enemy along.
+    // The enemy is enabled once the path has been completed.
+    fn movement(&self) -> &Movement;
+    fn movement_mut(&mut self) -> &mut Movement;
+
+    // Applies the difficulty profile, along with the enemy's overrides,
+    // to its attacks and health. Called by the Encounter before the stage starts
+    fn set_difficulty(&mut self, profile: DifficultyProfile);
+    fn difficulty_overrides(&self) -> &DifficultyOverrides;
+    fn difficulty_overrides_mut(&mut self) -> &mut DifficultyOverrides;
+
+    // Simulation state for save states, the node is handled by the Encounter
+    fn snapshot(&self) -> EnemySnapshot;
+    fn restore(&mut self, snapshot: &EnemySnapshot);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Player 1 on the left, player 2 on the right
+    fn players() -> [Vector2; 2] {
+        [Vector2::new(100.0, 300.0), Vector2::new(300.0, 300.0)]
+    }
+
+    #[test]
+    fn chooses_the_given_player() {
+        let players = players();
+        let targets = Targets::new(&players);
+        let from = Vector2::new(100.0, 0.0);
+        assert_eq!(targets.choose(0, from), Some(players[0]));
+        assert_eq!(targets.choose(1, from), Some(players[1]));
+    }
+
+    #[test]
+    fn minus_one_chooses_the_nearest() {
+        let players = players();
+        let targets = Targets::new(&players);
+        let left = Vector2::new(100.0, 0.0);
+        let right = Vector2::new(280.0, 0.0);
+        assert_eq!(targets.choose(-1, left), Some(players[0]));
+        assert_eq!(targets.choose(-1, right), Some(players[1]));
+    }
+
+    #[test]
+    fn players_not_playing_fall_back_to_the_nearest() {
+        let players = players();
+        let targets = Targets::new(&players);
+        let from = Vector2::new(280.0, 0.0);
+        assert_eq!(targets.choose(2, from), Some(players[1]));
+        assert_eq!(targets.choose(-5, from), Some(players[1]));
+
+        // Dead players are left out of the targets, so player 2
+        // dying sends its bullets at player 1
+        let alive = [players[0]];
+        let targets = Targets::new(&alive);
+        assert_eq!(targets.choose(1, from), Some(players[0]));
+
+        // Nobody left to aim at
+        assert_eq!(Targets::new(&[]).choose(0, from), None);
+    }
 }
@@ -0,0 +1,556 @@
+use gdnative::api::Path2D;
+use gdnative::api::Position2D;
+use gdnative::prelude::*;
+
+use crate::enemy::generic_enemy::GenericEnemy;
+use crate::save_state::MovementSnapshot;
+
+use std::f32::consts::PI;
+
+// Size of the playfield, enemies outside of it while exiting have escaped
+pub const PLAYFIELD_WIDTH: f32 = 480.0;
+pub const PLAYFIELD_HEIGHT: f32 = 270.0;
+
+// Kinds of path an enemy can follow to reach its attack position
+#[derive(Clone)]
+pub enum MovementPath {
+    // Straight line towards the goal at the movement speed
+    Approach { goal: Vector2 },
+    // Chain of cubic Bézier segments (p0, control 1, control 2, p3)
+    // travelled in `duration` seconds
+    Bezier {
+        segments: Vec<[Vector2; 4]>,
+        duration: f32,
+    },
+    // Catmull-Rom spline passing through every point,
+    // travelled in `duration` seconds
+    CatmullRom { points: Vec<Vector2>, duration: f32 },
+    // Straight lines between each point at the movement speed,
+    // pausing for `wait` seconds at every point
+    Waypoints { points: Vec<Vector2>, wait: f32 },
+}
+
+// Multiplier applied to the movement speed over time
+#[derive(Clone, Copy)]
+pub struct SpeedCurve {
+    pub from: f32,
+    pub to: f32,
+    // Time (secs) to go from `from` to `to`, 0 for a constant `to`
+    pub duration: f32,
+}
+
+impl SpeedCurve {
+    pub fn sample(&self, time: f32) -> f32 {
+        if self.duration <= 0.0 || time >= self.duration {
+            self.to
+        } else {
+            self.from + (self.to - self.from) * (time / self.duration)
+        }
+    }
+}
+
+// Editor facing configuration for a Movement, exposed as properties
+// on each enemy through `register_properties`
+pub struct MovementConfig {
+    // One of "approach", "bezier", "catmull_rom" or "waypoints"
+    pub kind: String,
+    // Movement speed (px/sec) for approach, waypoints and exiting
+    pub speed: f32,
+    // Time to travel a bezier or catmull_rom path (msec)
+    pub duration_ms: i64,
+    // Time spent at each waypoint (msec)
+    pub waypoint_wait_ms: i64,
+    // Horizontal sine drift once the path is finished
+    // 0 = no drift
+    pub drift_amplitude: f32,
+    pub drift_frequency: f32,
+    // Lifetime after arriving before retreating off the screen (msec)
+    // -1 = never
+    pub exit_after_ms: i64,
+    pub exit_direction: Vector2,
+    // Speed multiplier ramp, starting when the enemy spawns
+    pub speed_curve_from: f32,
+    pub speed_curve_to: f32,
+    pub speed_curve_ms: i64,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            kind: "approach".to_string(),
+            speed: 80.0,
+            duration_ms: 2000,
+            waypoint_wait_ms: 0,
+            drift_amplitude: 0.0,
+            drift_frequency: 0.5,
+            exit_after_ms: -1,
+            exit_direction: Vector2::new(0.0, -1.0),
+            speed_curve_from: 1.0,
+            speed_curve_to: 1.0,
+            speed_curve_ms: 0,
+        }
+    }
+}
+
+// Moves an enemy along its path, then drifts in place
+// until it is time to leave the screen
+#[derive(Default)]
+pub struct Movement {
+    pub config: MovementConfig,
+
+    path: Option<MovementPath>,
+    speed_curve: Option<SpeedCurve>,
+
+    // Time since the enemy started moving (secs)
+    elapsed: f32,
+    // Progress through a timed path (0.0 - 1.0)
+    progress: f32,
+    // Current target for Waypoints
+    waypoint: usize,
+    // Time left waiting at the current waypoint (secs)
+    wait_remaining: f32,
+
+    // Time the path was completed (secs), None while still moving
+    arrived_at: Option<f32>,
+    exiting: bool,
+}
+
+impl Movement {
+    // Builds the path from the configuration and the enemy's children:
+    // - "Goal" (Position2D): Target for approach, and fallback for other kinds
+    // - "Path" (Path2D): Points (and Bézier handles) for the other kinds
+    // Must be called from `_ready`, before the enemy starts moving
+    pub fn setup(&mut self, owner: &Node2D) {
+        let start = owner.global_position();
+        let goal = unsafe { owner.get_node_as::<Position2D>("Goal") }
+            .map(|goal| goal.global_position());
+
+        // Read the points of the curve in global coordinates as (position, in, out)
+        let mut curve_points = vec![];
+        if let Some(path) = unsafe { owner.get_node_as::<Path2D>("Path") } {
+            if let Some(curve) = path.curve() {
+                let curve = unsafe { curve.assume_safe() };
+                let origin = path.global_position();
+                for i in 0..curve.get_point_count() {
+                    curve_points.push((
+                        origin + curve.get_point_position(i),
+                        curve.get_point_in(i),
+                        curve.get_point_out(i),
+                    ));
+                }
+            }
+        }
+        if curve_points.is_empty() {
+            if let Some(goal) = goal {
+                curve_points.push((goal, Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)));
+            }
+        }
+        // Start from the enemy's spawn position unless the path already does
+        if curve_points.is_empty() || curve_points[0].0.distance_to(start) >= 1.0 {
+            curve_points.insert(0, (start, Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)));
+        }
+
+        let duration = self.config.duration_ms.max(1) as f32 / 1000.0;
+        let path = match self.config.kind.as_str() {
+            "bezier" => MovementPath::Bezier {
+                segments: curve_points
+                    .windows(2)
+                    .map(|x| [x[0].0, x[0].0 + x[0].2, x[1].0 + x[1].1, x[1].0])
+                    .collect(),
+                duration,
+            },
+            "catmull_rom" => MovementPath::CatmullRom {
+                points: curve_points.iter().map(|x| x.0).collect(),
+                duration,
+            },
+            "waypoints" => MovementPath::Waypoints {
+                points: curve_points.iter().skip(1).map(|x| x.0).collect(),
+                wait: self.config.waypoint_wait_ms.max(0) as f32 / 1000.0,
+            },
+            kind => {
+                if kind != "approach" {
+                    godot_warn!("Unknown movement kind {kind}, using approach");
+                }
+                let goal = goal.unwrap_or_else(|| curve_points.last().unwrap().0);
+                MovementPath::Approach { goal }
+            }
+        };
+
+        self.path = Some(path);
+        self.speed_curve = Some(SpeedCurve {
+            from: self.config.speed_curve_from,
+            to: self.config.speed_curve_to,
+            duration: self.config.speed_curve_ms.max(0) as f32 / 1000.0,
+        });
+    }
+
+    // True once the path has been completed
+    // Used to enable the enemy
+    pub fn has_arrived(&self) -> bool {
+        self.arrived_at.is_some()
+    }
+
+    // True once the enemy has started leaving the screen
+    pub fn is_exiting(&self) -> bool {
+        self.exiting
+    }
+
+    // True once an exiting enemy with the given hitbox radius
+    // is fully outside of the playfield
+    pub fn has_escaped(&self, pos: Vector2, radius: u32) -> bool {
+        let radius = radius as f32;
+        self.exiting
+            && (pos.x < -radius
+                || pos.y < -radius
+                || pos.x > PLAYFIELD_WIDTH + radius
+                || pos.y > PLAYFIELD_HEIGHT + radius)
+    }
+
+    // Advances the movement by `deltatime` and returns the new position
+    pub fn step(&mut self, pos: Vector2, deltatime: f32) -> Vector2 {
+        let multiplier = self
+            .speed_curve
+            .map(|curve| curve.sample(self.elapsed))
+            .unwrap_or(1.0);
+        let distance = self.config.speed * multiplier * deltatime;
+        self.elapsed += deltatime;
+
+        if self.exiting {
+            return pos + self.exit_direction() * distance;
+        }
+
+        if let Some(arrived_at) = self.arrived_at {
+            if self.config.exit_after_ms >= 0
+                && self.elapsed - arrived_at >= self.config.exit_after_ms as f32 / 1000.0
+            {
+                self.exiting = true;
+            }
+            return pos + self.drift(self.elapsed - arrived_at, deltatime);
+        }
+
+        let (new_pos, arrived) = match &self.path {
+            None => (pos, true),
+            Some(MovementPath::Approach { goal }) => Movement::move_towards(pos, *goal, distance),
+            Some(MovementPath::Bezier { segments, .. }) if segments.is_empty() => (pos, true),
+            Some(MovementPath::Bezier { segments, duration }) => {
+                self.progress = (self.progress + deltatime * multiplier / duration).min(1.0);
+                let (i, t) = Movement::segment(segments.len(), self.progress);
+                let [p0, p1, p2, p3] = segments[i];
+                (cubic_bezier(p0, p1, p2, p3, t), self.progress >= 1.0)
+            }
+            Some(MovementPath::CatmullRom { points, .. }) if points.len() < 2 => (pos, true),
+            Some(MovementPath::CatmullRom { points, duration }) => {
+                self.progress = (self.progress + deltatime * multiplier / duration).min(1.0);
+                let (i, t) = Movement::segment(points.len() - 1, self.progress);
+                // Repeat the end points to give the first and last segments a tangent
+                let p0 = points[i.saturating_sub(1)];
+                let p3 = points[(i + 2).min(points.len() - 1)];
+                (
+                    catmull_rom(p0, points[i], points[i + 1], p3, t),
+                    self.progress >= 1.0,
+                )
+            }
+            Some(MovementPath::Waypoints { points, wait }) => {
+                if self.waypoint >= points.len() {
+                    (pos, true)
+                } else if self.wait_remaining > 0.0 {
+                    self.wait_remaining -= deltatime;
+                    (pos, false)
+                } else {
+                    let (new_pos, reached) =
+                        Movement::move_towards(pos, points[self.waypoint], distance);
+                    if reached {
+                        self.waypoint += 1;
+                        self.wait_remaining = *wait;
+                    }
+                    (new_pos, reached && self.waypoint >= points.len())
+                }
+            }
+        };
+
+        if arrived {
+            self.arrived_at = Some(self.elapsed);
+        }
+
+        new_pos
+    }
+
+    // Progress along the path, the path itself is rebuilt by `setup`
+    pub fn snapshot(&self) -> MovementSnapshot {
+        MovementSnapshot {
+            elapsed: self.elapsed,
+            progress: self.progress,
+            waypoint: self.waypoint as u64,
+            wait_remaining: self.wait_remaining,
+            arrived_at: self.arrived_at,
+            exiting: self.exiting,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &MovementSnapshot) {
+        self.elapsed = snapshot.elapsed;
+        self.progress = snapshot.progress;
+        self.waypoint = snapshot.waypoint as usize;
+        self.wait_remaining = snapshot.wait_remaining;
+        self.arrived_at = snapshot.arrived_at;
+        self.exiting = snapshot.exiting;
+    }
+
+    // Unit vector to leave the screen along, straight up like the default
+    // when `exit_direction` is (0, 0) and has no direction to normalize
+    fn exit_direction(&self) -> Vector2 {
+        let direction = self.config.exit_direction;
+        if direction.length() > 0.0 {
+            direction.normalized()
+        } else {
+            Vector2::new(0.0, -1.0)
+        }
+    }
+
+    // Moves `pos` up to `distance` towards `goal`, returning if it was reached
+    fn move_towards(pos: Vector2, goal: Vector2, distance: f32) -> (Vector2, bool) {
+        let offset = goal - pos;
+        if offset.length() <= distance {
+            (goal, true)
+        } else {
+            (pos + offset.normalized() * distance, false)
+        }
+    }
+
+    // Splits progress through a path of `count` equal-time segments
+    // into (segment index, progress through segment)
+    fn segment(count: usize, progress: f32) -> (usize, f32) {
+        if count == 0 {
+            return (0, 1.0);
+        }
+        let scaled = progress * count as f32;
+        let i = (scaled as usize).min(count - 1);
+        (i, scaled - i as f32)
+    }
+
+    // Change in drift offset between `time` - `deltatime` and `time`
+    fn drift(&self, time: f32, deltatime: f32) -> Vector2 {
+        if self.config.drift_amplitude == 0.0 {
+            return Vector2::new(0.0, 0.0);
+        }
+        let omega = 2.0 * PI * self.config.drift_frequency;
+        let change = (omega * time).sin() - (omega * (time - deltatime)).sin();
+        Vector2::new(change * self.config.drift_amplitude, 0.0)
+    }
+}
+
+pub fn cubic_bezier(p0: Vector2, p1: Vector2, p2: Vector2, p3: Vector2, t: f32) -> Vector2 {
+    let u = 1.0 - t;
+    p0 * (u * u * u) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * (t * t * t)
+}
+
+// Uniform Catmull-Rom spline between p1 and p2
+pub fn catmull_rom(p0: Vector2, p1: Vector2, p2: Vector2, p3: Vector2, t: f32) -> Vector2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+// Registers the movement configuration as properties of an enemy
+pub fn register_properties<T>(builder: &ClassBuilder<T>)
+where
+    T: GenericEnemy<Base = Node2D>,
+{
+    builder
+        .property::<String>("movement/kind")
+        .with_default("approach".to_string())
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.kind.clone())
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| this.movement_mut().config.kind = v)
+        .done();
+    builder
+        .property::<f32>("movement/speed")
+        .with_default(80.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.speed)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| this.movement_mut().config.speed = v)
+        .done();
+    builder
+        .property::<i64>("movement/duration_ms")
+        .with_default(2000)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.duration_ms)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.duration_ms = v
+        })
+        .done();
+    builder
+        .property::<i64>("movement/waypoint_wait_ms")
+        .with_default(0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.waypoint_wait_ms)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.waypoint_wait_ms = v
+        })
+        .done();
+    builder
+        .property::<f32>("movement/drift_amplitude")
+        .with_default(0.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.drift_amplitude)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.drift_amplitude = v
+        })
+        .done();
+    builder
+        .property::<f32>("movement/drift_frequency")
+        .with_default(0.5)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.drift_frequency)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.drift_frequency = v
+        })
+        .done();
+    builder
+        .property::<i64>("movement/exit_after_ms")
+        .with_default(-1)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.exit_after_ms)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.exit_after_ms = v
+        })
+        .done();
+    builder
+        .property::<Vector2>("movement/exit_direction")
+        .with_default(Vector2::new(0.0, -1.0))
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.exit_direction)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.exit_direction = v
+        })
+        .done();
+    builder
+        .property::<f32>("movement/speed_curve_from")
+        .with_default(1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.speed_curve_from)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.speed_curve_from = v
+        })
+        .done();
+    builder
+        .property::<f32>("movement/speed_curve_to")
+        .with_default(1.0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.speed_curve_to)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.speed_curve_to = v
+        })
+        .done();
+    builder
+        .property::<i64>("movement/speed_curve_ms")
+        .with_default(0)
+        .with_getter(|this: &T, _owner: TRef<Node2D>| this.movement().config.speed_curve_ms)
+        .with_setter(|this: &mut T, _owner: TRef<Node2D>, v| {
+            this.movement_mut().config.speed_curve_ms = v
+        })
+        .done();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector2, b: Vector2) {
+        assert!(a.distance_to(b) < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn cubic_bezier_passes_through_its_end_points() {
+        let p0 = Vector2::new(0.0, 0.0);
+        let p1 = Vector2::new(10.0, 40.0);
+        let p2 = Vector2::new(50.0, -20.0);
+        let p3 = Vector2::new(60.0, 10.0);
+        assert_close(cubic_bezier(p0, p1, p2, p3, 0.0), p0);
+        assert_close(cubic_bezier(p0, p1, p2, p3, 1.0), p3);
+    }
+
+    #[test]
+    fn cubic_bezier_with_handles_on_the_line_is_the_line() {
+        let p0 = Vector2::new(0.0, 0.0);
+        let p3 = Vector2::new(30.0, 60.0);
+        let p1 = p0 + (p3 - p0) / 3.0;
+        let p2 = p0 + (p3 - p0) * (2.0 / 3.0);
+        assert_close(cubic_bezier(p0, p1, p2, p3, 0.5), Vector2::new(15.0, 30.0));
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_the_inner_points() {
+        let p0 = Vector2::new(-10.0, 5.0);
+        let p1 = Vector2::new(0.0, 0.0);
+        let p2 = Vector2::new(20.0, 10.0);
+        let p3 = Vector2::new(25.0, 40.0);
+        assert_close(catmull_rom(p0, p1, p2, p3, 0.0), p1);
+        assert_close(catmull_rom(p0, p1, p2, p3, 1.0), p2);
+    }
+
+    #[test]
+    fn catmull_rom_on_evenly_spaced_points_is_linear() {
+        let points: Vec<Vector2> = (0..4).map(|x| Vector2::new(x as f32 * 10.0, 0.0)).collect();
+        let pos = catmull_rom(points[0], points[1], points[2], points[3], 0.25);
+        assert_close(pos, Vector2::new(12.5, 0.0));
+    }
+
+    #[test]
+    fn segment_splits_progress_evenly() {
+        assert_eq!(Movement::segment(4, 0.0), (0, 0.0));
+        assert_eq!(Movement::segment(4, 0.5), (2, 0.0));
+        let (i, t) = Movement::segment(4, 0.375);
+        assert_eq!(i, 1);
+        assert!((t - 0.5).abs() < 1e-6);
+        // The end of the path stays within the last segment
+        assert_eq!(Movement::segment(4, 1.0), (3, 1.0));
+        assert_eq!(Movement::segment(0, 0.5), (0, 1.0));
+    }
+
+    #[test]
+    fn move_towards_stops_at_the_goal() {
+        let goal = Vector2::new(10.0, 0.0);
+        let (pos, reached) = Movement::move_towards(Vector2::new(0.0, 0.0), goal, 4.0);
+        assert_close(pos, Vector2::new(4.0, 0.0));
+        assert!(!reached);
+        let (pos, reached) = Movement::move_towards(Vector2::new(8.0, 0.0), goal, 4.0);
+        assert_close(pos, goal);
+        assert!(reached);
+    }
+
+    #[test]
+    fn exiting_enemy_escapes_past_the_margin() {
+        let mut movement = Movement::default();
+        movement.config.exit_after_ms = 0;
+        let radius = 16;
+        let mut pos = Vector2::new(100.0, 20.0);
+        let mut steps = 0;
+        while !movement.has_escaped(pos, radius) {
+            // Still within the margin of its hitbox above the playfield
+            assert!(pos.y >= -16.0);
+            pos = movement.step(pos, 0.1);
+            steps += 1;
+            assert!(steps < 100, "never escaped");
+        }
+        assert!(movement.is_exiting());
+        assert!(pos.y < -16.0);
+    }
+
+    #[test]
+    fn enemy_that_never_exits_never_escapes() {
+        let mut movement = Movement::default();
+        let mut pos = Vector2::new(100.0, 20.0);
+        for _ in 0..600 {
+            pos = movement.step(pos, 0.1);
+        }
+        assert!(!movement.is_exiting());
+        assert!(!movement.has_escaped(pos, 16));
+        // Only exiting enemies escape, not ones spawned above the screen
+        assert!(!movement.has_escaped(Vector2::new(100.0, -40.0), 16));
+    }
+
+    #[test]
+    fn exiting_with_a_zero_direction_moves_straight_up() {
+        let mut movement = Movement::default();
+        movement.config.exit_direction = Vector2::new(0.0, 0.0);
+        movement.exiting = true;
+        let pos = movement.step(Vector2::new(100.0, 100.0), 0.5);
+        assert!(pos.x.is_finite() && pos.y.is_finite());
+        assert_close(pos, Vector2::new(100.0, 60.0));
+    }
+}
@@ -1,22 +1,35 @@
 use gdnative::api::Node2D;
-use gdnative::api::Position2D;
-use gdnative::api::OS;
 use gdnative::prelude::*;
 
 use crate::bullet_manager::BulletManager;
+use crate::difficulty::{self, DifficultyOverrides, DifficultyProfile};
+use crate::laser::LaserConfig;
+use crate::rank::RankScale;
 
-use crate::enemy::generic_enemy::GenericEnemy;
+use crate::enemy::generic_enemy::{self, GenericEnemy, Targets};
+use crate::enemy::movement::{self, Movement};
+use crate::save_state::EnemySnapshot;
+use crate::signals;
 
 use std::f32::consts::PI;
 
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct Orb {
     // Switches the direction the attack rotates
     #[property(default = false)]
     rotate_direction: bool,
+    // Touching the orb hits the player
+    #[property(default = true)]
+    contact_damage: bool,
+    // Time between lasers aimed at the nearest player (msec)
+    // -1 = no lasers
+    #[property(default = -1)]
+    laser_interval_ms: i64,
 
     // Primary attack status
+    time: f32, // Simulation time the enemy has been attacking (msec)
     last_attack: i64, // Time of last attack (msec)
     attack_timeout_ms: i64, // Time between attacks (msec)
     
@@ -26,7 +39,11 @@ pub struct Orb {
     // Metadata for trait GenericEnemy
     health: u32,
     enabled: bool,
-    goal_position: Vector2,
+    escaped: bool,
+    movement: Movement,
+    difficulty_overrides: DifficultyOverrides,
+    // Difficulty profile with the overrides applied
+    difficulty: DifficultyProfile,
 }
 
 #[methods]
@@ -34,37 +51,50 @@ impl Orb {
     fn new(_owner: &Node2D) -> Self {
         Self {
             rotate_direction: false,
+            contact_damage: true,
+            laser_interval_ms: -1,
 
+            time: 0.0,
             last_attack: 0,
             attack_timeout_ms: 500,
             
             bullet_speed: 50.0,
             attack_offset: 0.0,
 
-            health: 1,
+            health: Self::BASE_HEALTH,
             enabled: false,
-            goal_position: Vector2::new(0.0, 0.0),
+            escaped: false,
+            movement: Movement::default(),
+            difficulty_overrides: DifficultyOverrides::default(),
+            difficulty: DifficultyProfile::default(),
         }
     }
 
+    fn register(builder: &ClassBuilder<Self>) {
+        movement::register_properties(builder);
+        difficulty::register_overrides(builder);
+        generic_enemy::register_signals(builder);
+    }
+
     #[export]
     fn _ready(&mut self, owner: &Node2D) {
-        // Fetch the position of the Goal, where the Orb moves to before attacking
-        let pos = unsafe { owner.get_node_as::<Position2D>("./Goal").unwrap() };
-        self.goal_position = pos.global_position();
+        // Build the path from the Goal (and optional Path) children
+        self.movement.setup(owner);
     }
 }
 
 impl GenericEnemy for Orb {
     // Radius of the hitbox
     const HITBOX_SIZE: u32 = 9;
+    const BASE_HEALTH: u32 = 1;
 
     fn tick(
         &mut self,
         owner: &Node2D,
         bullet_handler: &TInstance<'static, BulletManager, Shared>,
-        _player_pos: Vector2,
-        _deltatime: f32,
+        targets: Targets,
+        rank: RankScale,
+        deltatime: f32,
     ) {
         // Prevent ticking if not enabled
         if !self.enabled {
@@ -72,10 +102,17 @@ impl GenericEnemy for Orb {
         }
 
         // Handle primary attack
-        let now = OS::godot_singleton().get_ticks_msec();
-        if now - self.last_attack > self.attack_timeout_ms {
-            for i in 0..9 {
-                let angle = ((i as f32 * 40.0) + self.attack_offset) * PI / 180.0;
+        let before = self.time as i64;
+        self.time += deltatime * 1000.0;
+        let now = self.time as i64;
+        let difficulty = rank.apply(self.difficulty);
+        if now - self.last_attack > difficulty.attack_timeout(self.attack_timeout_ms) {
+            let bullet_speed = difficulty.bullet_speed(self.bullet_speed);
+            // Evenly spaced ring of bullets
+            let count = self.difficulty.bullet_count(9);
+            let spacing = 360.0 / count as f32;
+            for i in 0..count {
+                let angle = ((i as f32 * spacing) + self.attack_offset) * PI / 180.0;
 
                 let pos = owner.get_global_transform().origin;
 
@@ -86,8 +123,8 @@ impl GenericEnemy for Orb {
                             "orb_bullet".to_string(),
                             pos.x, // + angle.cos() * 15.0,
                             pos.y, // + angle.sin() * 15.0,
-                            angle.cos() * self.bullet_speed,
-                            angle.sin() * self.bullet_speed,
+                            angle.cos() * bullet_speed,
+                            angle.sin() * bullet_speed,
                         )
                     })
                     .unwrap();
@@ -101,6 +138,27 @@ impl GenericEnemy for Orb {
                 self.attack_offset = (self.attack_offset - 5.0) % 360.0;
             }
         }
+
+        // Lasers fire each time the simulation time passes a multiple of the interval,
+        // so they need no state of their own in save states
+        if self.laser_interval_ms > 0
+            && now / self.laser_interval_ms > before / self.laser_interval_ms
+        {
+            let pos = owner.global_position();
+            let mut config = LaserConfig {
+                origin: pos,
+                ..Default::default()
+            };
+            if let Some(target) = targets.nearest(pos) {
+                let aim = target - pos;
+                config.angle = aim.y.atan2(aim.x);
+            }
+            bullet_handler
+                .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                    x.spawn_laser(node.as_ref(), config)
+                })
+                .unwrap();
+        }
     }
 
     // Decrement health when hit by a bullet
@@ -108,10 +166,12 @@ impl GenericEnemy for Orb {
     fn hit(&mut self, owner: &Node2D) -> bool {
         if self.health != 0 {
             self.health -= 1;
+            signals::emit_deferred(owner, "damaged", &[self.health.to_variant()]);
 
             if self.health == 0 {
                 self.enabled = false;
                 owner.set_visible(false);
+                signals::emit_deferred(owner, "killed", &[]);
             }
 
             true
@@ -129,11 +189,65 @@ impl GenericEnemy for Orb {
         self.enabled = enabled;
     }
 
-    fn goal_position(&self, _owner: &Node2D) -> Vector2 {
-        self.goal_position
+    fn contact_damage(&self) -> bool {
+        self.contact_damage
+    }
+
+    fn movement(&self) -> &Movement {
+        &self.movement
+    }
+
+    fn movement_mut(&mut self) -> &mut Movement {
+        &mut self.movement
     }
 
     fn is_killed(&self) -> bool {
         self.health == 0
     }
+
+    fn is_escaped(&self) -> bool {
+        self.escaped
+    }
+
+    fn set_escaped(&mut self, escaped: bool) {
+        self.escaped = escaped;
+        if escaped {
+            self.enabled = false;
+        }
+    }
+
+    fn set_difficulty(&mut self, profile: DifficultyProfile) {
+        self.difficulty = profile.with_overrides(&self.difficulty_overrides);
+        self.health = self.difficulty.health(Self::BASE_HEALTH);
+    }
+
+    fn difficulty_overrides(&self) -> &DifficultyOverrides {
+        &self.difficulty_overrides
+    }
+
+    fn difficulty_overrides_mut(&mut self) -> &mut DifficultyOverrides {
+        &mut self.difficulty_overrides
+    }
+
+    fn snapshot(&self) -> EnemySnapshot {
+        EnemySnapshot {
+            health: self.health,
+            enabled: self.enabled,
+            escaped: self.escaped,
+            time: self.time,
+            last_attack: self.last_attack,
+            attack_offset: self.attack_offset,
+            movement: self.movement.snapshot(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &EnemySnapshot) {
+        self.health = snapshot.health;
+        self.enabled = snapshot.enabled;
+        self.escaped = snapshot.escaped;
+        self.time = snapshot.time;
+        self.last_attack = snapshot.last_attack;
+        self.attack_offset = snapshot.attack_offset;
+        self.movement.restore(&snapshot.movement);
+    }
 }
@@ -1,17 +1,29 @@
 use gdnative::api::Node2D;
-use gdnative::api::Position2D;
-use gdnative::api::OS;
 use gdnative::prelude::*;
 
 use crate::bullet_manager::BulletManager;
+use crate::difficulty::{self, DifficultyOverrides, DifficultyProfile};
+use crate::rank::RankScale;
 
-use crate::enemy::generic_enemy::GenericEnemy;
+use crate::enemy::generic_enemy::{self, GenericEnemy, Targets};
+use crate::enemy::movement::{self, Movement};
+use crate::save_state::EnemySnapshot;
+use crate::signals;
 
 use std::f32::consts::PI;
 
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct SmallOrb {
+    // Touching the orb hits the player
+    #[property(default = true)]
+    contact_damage: bool,
+    // Player aimed at, 0 for player 1 and -1 for whichever is nearest
+    #[property(default = -1)]
+    target_player: i64,
+
+    time: f32, // Simulation time the enemy has been attacking (msec)
     last_attack: i64, // Time of last attack (msec)
     attack_timeout_ms: i64, // Time between attacks (msec)
     
@@ -20,42 +32,60 @@ pub struct SmallOrb {
     // Metadata for trait GenericEnemy
     health: u32,
     enabled: bool,
-    goal_position: Vector2,
+    escaped: bool,
+    movement: Movement,
+    difficulty_overrides: DifficultyOverrides,
+    // Difficulty profile with the overrides applied
+    difficulty: DifficultyProfile,
 }
 
 #[methods]
 impl SmallOrb {
     fn new(_owner: &Node2D) -> Self {
         Self {
+            contact_damage: true,
+            target_player: -1,
+
+            time: 0.0,
             last_attack: 0,
             attack_timeout_ms: 500,
             
             bullet_speed: 50.0,
 
-            health: 1,
+            health: Self::BASE_HEALTH,
             enabled: false,
-            goal_position: Vector2::new(0.0, 0.0),
+            escaped: false,
+            movement: Movement::default(),
+            difficulty_overrides: DifficultyOverrides::default(),
+            difficulty: DifficultyProfile::default(),
         }
     }
 
+    fn register(builder: &ClassBuilder<Self>) {
+        movement::register_properties(builder);
+        difficulty::register_overrides(builder);
+        generic_enemy::register_signals(builder);
+    }
+
     #[export]
     fn _ready(&mut self, owner: &Node2D) {
-        // Fetch the position of the Goal position
-        let pos = unsafe { owner.get_node_as::<Position2D>("Goal").unwrap() };
-        self.goal_position = pos.global_position();
+        // Build the path from the Goal (and optional Path) children
+        self.movement.setup(owner);
     }
 }
 
 impl GenericEnemy for SmallOrb {
     // Radius of the hitbox
     const HITBOX_SIZE: u32 = 4;
+    const BASE_HEALTH: u32 = 1;
 
     fn tick(
         &mut self,
         owner: &Node2D,
         bullet_handler: &TInstance<'static, BulletManager, Shared>,
-        player_pos: Vector2,
-        _deltatime: f32,
+        targets: Targets,
+        rank: RankScale,
+        deltatime: f32,
     ) {
         // Prevent ticking when disabled
         if !self.enabled {
@@ -63,17 +93,21 @@ impl GenericEnemy for SmallOrb {
         }
 
         // Handle primary attack
-        let now = OS::godot_singleton().get_ticks_msec();
-        if now - self.last_attack > self.attack_timeout_ms {
-            for i in 0..3 {
-                let pos = owner.get_global_transform().origin;
-
+        self.time += deltatime * 1000.0;
+        let now = self.time as i64;
+        let difficulty = rank.apply(self.difficulty);
+        if now - self.last_attack > difficulty.attack_timeout(self.attack_timeout_ms) {
+            let bullet_speed = difficulty.bullet_speed(self.bullet_speed);
+            let pos = owner.get_global_transform().origin;
+            let player_pos = match targets.choose(self.target_player, pos) {
+                Some(player_pos) => player_pos,
+                None => return,
+            };
+            // Spread of bullets 15 degrees apart, centered on the player
+            let count = self.difficulty.aimed_bullet_count(3);
+            for i in 0..count {
                 let mut angle = (-pos.y + player_pos.y).atan2(-pos.x + player_pos.x);
-                if i == 0 {
-                    angle -= 15.0 * PI / 180.0;
-                } else if i == 2 {
-                    angle += 15.0 * PI / 180.0;
-                }
+                angle += (i as f32 - (count - 1) as f32 / 2.0) * 15.0 * PI / 180.0;
 
                 bullet_handler
                     .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
@@ -82,8 +116,8 @@ impl GenericEnemy for SmallOrb {
                             "orb_bullet".to_string(),
                             pos.x + angle.cos() * 5.0,
                             pos.y + angle.sin() * 5.0,
-                            angle.cos() * self.bullet_speed,
-                            angle.sin() * self.bullet_speed,
+                            angle.cos() * bullet_speed,
+                            angle.sin() * bullet_speed,
                         )
                     })
                     .unwrap();
@@ -98,10 +132,12 @@ impl GenericEnemy for SmallOrb {
     fn hit(&mut self, owner: &Node2D) -> bool {
         if self.health != 0 {
             self.health -= 1;
+            signals::emit_deferred(owner, "damaged", &[self.health.to_variant()]);
 
             if self.health == 0 {
                 self.enabled = false;
                 owner.set_visible(false);
+                signals::emit_deferred(owner, "killed", &[]);
             }
 
             true
@@ -119,11 +155,64 @@ impl GenericEnemy for SmallOrb {
         self.enabled = enabled;
     }
 
-    fn goal_position(&self, _owner: &Node2D) -> Vector2 {
-        self.goal_position
+    fn contact_damage(&self) -> bool {
+        self.contact_damage
+    }
+
+    fn movement(&self) -> &Movement {
+        &self.movement
+    }
+
+    fn movement_mut(&mut self) -> &mut Movement {
+        &mut self.movement
     }
 
     fn is_killed(&self) -> bool {
         self.health == 0
     }
+
+    fn is_escaped(&self) -> bool {
+        self.escaped
+    }
+
+    fn set_escaped(&mut self, escaped: bool) {
+        self.escaped = escaped;
+        if escaped {
+            self.enabled = false;
+        }
+    }
+
+    fn set_difficulty(&mut self, profile: DifficultyProfile) {
+        self.difficulty = profile.with_overrides(&self.difficulty_overrides);
+        self.health = self.difficulty.health(Self::BASE_HEALTH);
+    }
+
+    fn difficulty_overrides(&self) -> &DifficultyOverrides {
+        &self.difficulty_overrides
+    }
+
+    fn difficulty_overrides_mut(&mut self) -> &mut DifficultyOverrides {
+        &mut self.difficulty_overrides
+    }
+
+    fn snapshot(&self) -> EnemySnapshot {
+        EnemySnapshot {
+            health: self.health,
+            enabled: self.enabled,
+            escaped: self.escaped,
+            time: self.time,
+            last_attack: self.last_attack,
+            attack_offset: 0.0,
+            movement: self.movement.snapshot(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &EnemySnapshot) {
+        self.health = snapshot.health;
+        self.enabled = snapshot.enabled;
+        self.escaped = snapshot.escaped;
+        self.time = snapshot.time;
+        self.last_attack = snapshot.last_attack;
+        self.movement.restore(&snapshot.movement);
+    }
 }
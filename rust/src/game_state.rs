@@ -0,0 +1,231 @@
+use gdnative::export::PropertyUsage;
+use gdnative::prelude::*;
+
+use crate::difficulty::Difficulty;
+use crate::encounter_manager::{self, EncounterManager};
+use crate::node_paths;
+use crate::player::Player;
+use crate::signals;
+
+// Everything shown on the HUD
+#[derive(Clone, Copy, PartialEq, Default)]
+struct HudData {
+    score: i64,
+    hi_score: i64,
+    lives: i64,
+    bombs: i64,
+    power: i64,
+    graze: i64,
+    // Player 2 values are -1 in single player
+    p2_score: i64,
+    p2_lives: i64,
+    p2_bombs: i64,
+    p2_power: i64,
+    p2_graze: i64,
+    // Boss values are -1 while no boss is active
+    boss_health: i64,
+    boss_max_health: i64,
+    boss_time_ms: i64,
+}
+
+impl HudData {
+    // Provides the list of HUD properties exposed to Godot
+    fn properties() -> Vec<&'static str> {
+        vec![
+            "score",
+            "hi_score",
+            "lives",
+            "bombs",
+            "power",
+            "graze",
+            "p2_score",
+            "p2_lives",
+            "p2_bombs",
+            "p2_power",
+            "p2_graze",
+            "boss_health",
+            "boss_max_health",
+            "boss_time_ms",
+        ]
+    }
+
+    fn get(&self, name: &str) -> i64 {
+        match name {
+            "score" => self.score,
+            "hi_score" => self.hi_score,
+            "lives" => self.lives,
+            "bombs" => self.bombs,
+            "power" => self.power,
+            "graze" => self.graze,
+            "p2_score" => self.p2_score,
+            "p2_lives" => self.p2_lives,
+            "p2_bombs" => self.p2_bombs,
+            "p2_power" => self.p2_power,
+            "p2_graze" => self.p2_graze,
+            "boss_health" => self.boss_health,
+            "boss_max_health" => self.boss_max_health,
+            "boss_time_ms" => self.boss_time_ms,
+            _ => unreachable!("Unknown HUD property {name}"),
+        }
+    }
+}
+
+// Collects the state of the Player and EncounterManager into one
+// object for the HUD to bind to, emitting `changed` when anything differs
+#[derive(NativeClass, Default)]
+#[inherit(Node)]
+#[register_with(Self::register)]
+pub struct GameState {
+    // Hi-score to beat at the start of the run
+    #[property(default = 0)]
+    starting_hi_score: i64,
+
+    // Nodes the HUD values are read from, the players being looked up
+    // from the encounter manager
+    #[property]
+    encounter_manager_path: NodePath,
+    player: Option<TInstance<'static, Player, Shared>>,
+    player_2: Option<TInstance<'static, Player, Shared>>,
+    encounter_manager: Option<TInstance<'static, EncounterManager, Shared>>,
+
+    data: HudData,
+    // Recorded alongside the score, as scores are only comparable on the same difficulty
+    difficulty: Difficulty,
+}
+
+#[methods]
+impl GameState {
+    // Produces a read-only property for each value in HudData
+    fn register(builder: &ClassBuilder<Self>) {
+        for name in HudData::properties() {
+            builder
+                .property::<i64>(name)
+                .with_usage(PropertyUsage::EDITOR)
+                .with_getter(move |this: &GameState, _owner: TRef<Node>| this.data.get(name))
+                .done();
+        }
+
+        builder
+            .property::<String>("difficulty")
+            .with_usage(PropertyUsage::EDITOR)
+            .with_getter(|this: &GameState, _owner: TRef<Node>| {
+                this.difficulty.name().to_string()
+            })
+            .done();
+
+        builder.signal("changed").done();
+    }
+
+    fn new(_owner: &Node) -> Self {
+        Self {
+            encounter_manager_path: NodePath::from_str("../Encounters"),
+            ..Default::default()
+        }
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Node) {
+        self.encounter_manager = node_paths::fetch_instance(
+            owner,
+            &self.encounter_manager_path,
+            "EncounterManager",
+        );
+        let mut players = encounter_manager::players(&self.encounter_manager)
+            .unwrap_or_default()
+            .into_iter();
+        self.player = players.next();
+        self.player_2 = players.next();
+        node_paths::require(
+            owner,
+            self.player.is_some() && self.encounter_manager.is_some(),
+        );
+        self.data.hi_score = self.starting_hi_score;
+
+        if let Some(encounter_manager) = &self.encounter_manager {
+            self.difficulty = encounter_manager
+                .map(|x: &EncounterManager, _node: TRef<Node2D>| x.difficulty())
+                .unwrap();
+        }
+    }
+
+    // Entry for the high score table, with the values a score depends on
+    #[export]
+    fn high_score_entry(&self, _owner: &Node) -> Dictionary {
+        let entry = Dictionary::new();
+        entry.insert("score", self.data.score);
+        entry.insert("graze", self.data.graze);
+        entry.insert("difficulty", self.difficulty.name());
+        if let Some(player) = &self.player {
+            let ship = player.map(|x: &Player, _node: TRef<Node2D>| x.ship()).unwrap();
+            entry.insert("ship", ship.name());
+        }
+        // Co-op scores are recorded together
+        if let Some(player) = &self.player_2 {
+            let ship = player.map(|x: &Player, _node: TRef<Node2D>| x.ship()).unwrap();
+            entry.insert("p2_score", self.data.p2_score);
+            entry.insert("p2_graze", self.data.p2_graze);
+            entry.insert("p2_ship", ship.name());
+        }
+        entry.into_shared()
+    }
+
+    #[export]
+    fn _process(&mut self, owner: &Node, _deltatime: f32) {
+        let mut data = self
+            .player
+            .as_ref()
+            .unwrap()
+            .map(|x: &Player, node: TRef<Node2D>| HudData {
+                score: x.score(node.as_ref()),
+                lives: x.lives(node.as_ref()),
+                bombs: x.bombs(node.as_ref()),
+                power: x.power(node.as_ref()),
+                graze: x.graze_count(node.as_ref()),
+                ..Default::default()
+            })
+            .unwrap();
+        match &self.player_2 {
+            Some(player) => player
+                .map(|x: &Player, node: TRef<Node2D>| {
+                    data.p2_score = x.score(node.as_ref());
+                    data.p2_lives = x.lives(node.as_ref());
+                    data.p2_bombs = x.bombs(node.as_ref());
+                    data.p2_power = x.power(node.as_ref());
+                    data.p2_graze = x.graze_count(node.as_ref());
+                })
+                .unwrap(),
+            None => {
+                data.p2_score = -1;
+                data.p2_lives = -1;
+                data.p2_bombs = -1;
+                data.p2_power = -1;
+                data.p2_graze = -1;
+            }
+        }
+        data.hi_score = self.data.hi_score.max(data.score).max(data.p2_score);
+
+        let boss = self
+            .encounter_manager
+            .as_ref()
+            .unwrap()
+            .map(|x: &EncounterManager, _node: TRef<Node2D>| x.boss_status())
+            .unwrap();
+        match boss {
+            Some(boss) => {
+                data.boss_health = boss.health as i64;
+                data.boss_max_health = boss.max_health as i64;
+                data.boss_time_ms = boss.time_remaining_ms;
+            }
+            None => {
+                data.boss_health = -1;
+                data.boss_max_health = -1;
+                data.boss_time_ms = -1;
+            }
+        }
+
+        if data != self.data {
+            self.data = data;
+            signals::emit_deferred(owner, "changed", &[]);
+        }
+    }
+}
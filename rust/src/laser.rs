@@ -0,0 +1,245 @@
+use gdnative::api::Line2D;
+use gdnative::prelude::*;
+
+use std::f32::consts::PI;
+
+// Points used to draw and collide a curvy laser
+const CURVE_SEGMENTS: usize = 16;
+
+// Description of a laser, as passed to BulletManager::spawn_laser
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LaserConfig {
+    // Start of the laser (px) and its direction (radians)
+    pub origin: Vector2,
+    pub angle: f32,
+    // Size once fully grown (px)
+    pub length: f32,
+    pub width: f32,
+    // Duration of the harmless warning line (msec)
+    pub warning_ms: i64,
+    // Duration of the damaging beam (msec)
+    pub active_ms: i64,
+    // Time for the beam to extend and widen at the start of the active
+    // phase, and to narrow at the end of it (msec)
+    pub grow_ms: i64,
+    // Change in angle over time (radians/sec)
+    pub rotation_speed: f32,
+    // Sideways sine wave along the beam for curvy lasers
+    // 0 = straight
+    pub wave_amplitude: f32,
+    // Distance between wave peaks (px)
+    pub wave_length: f32,
+    // Speed the wave travels along the beam (waves/sec)
+    pub wave_speed: f32,
+}
+
+impl Default for LaserConfig {
+    fn default() -> Self {
+        Self {
+            origin: Vector2::new(0.0, 0.0),
+            angle: PI / 2.0,
+            length: 300.0,
+            width: 8.0,
+            warning_ms: 800,
+            active_ms: 1500,
+            grow_ms: 150,
+            rotation_speed: 0.0,
+            wave_amplitude: 0.0,
+            wave_length: 60.0,
+            wave_speed: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LaserPhase {
+    // Thin line showing where the beam will be, doesn't collide
+    Warning,
+    // Damaging beam
+    Active,
+    // Over, to be removed
+    Finished,
+}
+
+impl LaserConfig {
+    // Phase of a laser `age` secs after it was spawned
+    pub fn phase(&self, age: f32) -> LaserPhase {
+        let age_ms = age * 1000.0;
+        if age_ms < self.warning_ms as f32 {
+            LaserPhase::Warning
+        } else if age_ms < (self.warning_ms + self.active_ms) as f32 {
+            LaserPhase::Active
+        } else {
+            LaserPhase::Finished
+        }
+    }
+
+    // Progress (0.0 - 1.0) of growing at the start of the active
+    // phase, and shrinking at its end
+    fn growth(&self, age: f32) -> f32 {
+        let grow = self.grow_ms.max(1) as f32;
+        let active = age * 1000.0 - self.warning_ms as f32;
+        let remaining = self.active_ms as f32 - active;
+        (active / grow).min(remaining / grow).clamp(0.0, 1.0)
+    }
+
+    // Current length of the beam, the warning line shows the full length
+    pub fn length(&self, age: f32) -> f32 {
+        match self.phase(age) {
+            LaserPhase::Warning => self.length,
+            LaserPhase::Active => {
+                let grow = self.grow_ms.max(1) as f32;
+                let active = age * 1000.0 - self.warning_ms as f32;
+                self.length * (active / grow).clamp(0.0, 1.0)
+            }
+            LaserPhase::Finished => 0.0,
+        }
+    }
+
+    // Current width of the beam, or of the warning line
+    pub fn width(&self, age: f32, warning_width: f32) -> f32 {
+        match self.phase(age) {
+            LaserPhase::Warning => warning_width,
+            LaserPhase::Active => warning_width.max(self.width * self.growth(age)),
+            LaserPhase::Finished => 0.0,
+        }
+    }
+
+    // Points along the beam in global coordinates
+    pub fn points(&self, age: f32) -> Vec<Vector2> {
+        let angle = self.angle + self.rotation_speed * age;
+        let direction = Vector2::new(angle.cos(), angle.sin());
+        let normal = Vector2::new(-direction.y, direction.x);
+        let length = self.length(age);
+
+        let segments = if self.wave_amplitude == 0.0 {
+            1
+        } else {
+            CURVE_SEGMENTS
+        };
+        let wave_length = self.wave_length.max(1.0);
+        (0..=segments)
+            .map(|i| {
+                let distance = length * i as f32 / segments as f32;
+                let phase = distance / wave_length - self.wave_speed * age;
+                let offset = self.wave_amplitude * (2.0 * PI * phase).sin();
+                self.origin + direction * distance + normal * offset
+            })
+            .collect()
+    }
+}
+
+pub struct Laser {
+    pub config: LaserConfig,
+    pub node: Ref<Line2D, Shared>,
+    // Simulation time since the laser was spawned (secs)
+    pub age: f32,
+}
+
+impl Laser {
+    pub fn phase(&self) -> LaserPhase {
+        self.config.phase(self.age)
+    }
+
+    pub fn width(&self, warning_width: f32) -> f32 {
+        self.config.width(self.age, warning_width)
+    }
+
+    pub fn points(&self) -> Vec<Vector2> {
+        self.config.points(self.age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARNING_WIDTH: f32 = 1.0;
+
+    // Timings chosen to be exact in binary, so ages land on the phase boundaries
+    fn config() -> LaserConfig {
+        LaserConfig {
+            origin: Vector2::new(100.0, 50.0),
+            angle: 0.0,
+            length: 300.0,
+            width: 8.0,
+            warning_ms: 500,
+            active_ms: 1000,
+            grow_ms: 250,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn phases_change_at_their_durations() {
+        let config = config();
+        assert_eq!(config.phase(0.0), LaserPhase::Warning);
+        assert_eq!(config.phase(0.499), LaserPhase::Warning);
+        assert_eq!(config.phase(0.5), LaserPhase::Active);
+        assert_eq!(config.phase(1.499), LaserPhase::Active);
+        assert_eq!(config.phase(1.5), LaserPhase::Finished);
+    }
+
+    #[test]
+    fn warning_line_is_thin_and_full_length() {
+        let config = config();
+        for age in [0.0, 0.25, 0.499] {
+            assert_eq!(config.width(age, WARNING_WIDTH), WARNING_WIDTH);
+            assert_eq!(config.length(age), 300.0);
+        }
+    }
+
+    #[test]
+    fn beam_grows_then_shrinks() {
+        let config = config();
+        // Starts no thinner than the warning line
+        assert_eq!(config.width(0.5, WARNING_WIDTH), WARNING_WIDTH);
+        assert_eq!(config.length(0.5), 0.0);
+        // Halfway through growing
+        assert_eq!(config.width(0.625, WARNING_WIDTH), 4.0);
+        assert_eq!(config.length(0.625), 150.0);
+        // Fully grown after grow_ms
+        assert_eq!(config.width(0.75, WARNING_WIDTH), 8.0);
+        assert_eq!(config.length(0.75), 300.0);
+        assert_eq!(config.width(1.0, WARNING_WIDTH), 8.0);
+        // Narrows over the last grow_ms, keeping its length
+        assert_eq!(config.width(1.375, WARNING_WIDTH), 4.0);
+        assert_eq!(config.length(1.375), 300.0);
+
+        assert_eq!(config.width(1.5, WARNING_WIDTH), 0.0);
+        assert_eq!(config.length(1.5), 0.0);
+    }
+
+    #[test]
+    fn straight_beam_has_two_points() {
+        assert_eq!(
+            config().points(1.0),
+            vec![Vector2::new(100.0, 50.0), Vector2::new(400.0, 50.0)]
+        );
+    }
+
+    #[test]
+    fn curvy_beam_follows_the_wave() {
+        let config = LaserConfig {
+            wave_amplitude: 10.0,
+            ..config()
+        };
+        let points = config.points(1.0);
+        assert_eq!(points.len(), CURVE_SEGMENTS + 1);
+        assert_eq!(points[0], Vector2::new(100.0, 50.0));
+        // Never further from the straight beam than the amplitude
+        assert!(points.iter().all(|x| (x.y - 50.0).abs() <= 10.0 + 0.001));
+        assert!(points.iter().any(|x| (x.y - 50.0).abs() > 1.0));
+    }
+
+    #[test]
+    fn rotates_over_time() {
+        let config = LaserConfig {
+            rotation_speed: PI / 2.0,
+            ..config()
+        };
+        // A quarter turn after a second, pointing down
+        let end = config.points(1.0)[1];
+        assert!(end.distance_to(Vector2::new(100.0, 350.0)) < 0.01);
+    }
+}
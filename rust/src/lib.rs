@@ -1,14 +1,34 @@
+mod bullet_handle;
 mod bullet_manager;
+mod bullet_motion;
+mod collision;
 mod custom_encounter;
+mod difficulty;
 mod encounter;
 mod encounter_manager;
 mod enemy;
+mod game_state;
+mod laser;
+mod node_paths;
 mod player;
+mod player_options;
+mod rank;
+mod rng;
+mod save_state;
+mod ship;
+mod signals;
+mod time_scale;
 
 use gdnative::prelude::*;
 
 // Function that registers all exposed classes to Godot
 fn init(handle: InitHandle) {
+    // Scales time for pause, slow-motion and hit-stop
+    handle.add_class::<time_scale::TimeScale>();
+
+    // Seeded random numbers for patterns, drops and effects
+    handle.add_class::<rng::Rng>();
+
     // Manages the movement of all bullets
     handle.add_class::<bullet_manager::BulletManager>();
 
@@ -25,6 +45,17 @@ fn init(handle: InitHandle) {
 
     // The player
     handle.add_class::<player::Player>();
+    // Satellite units following the player
+    handle.add_class::<player_options::PlayerOptions>();
+
+    // Dynamic rank adjusting enemy patterns to the player's performance
+    handle.add_class::<rank::Rank>();
+
+    // Aggregated state for the HUD
+    handle.add_class::<game_state::GameState>();
+
+    // Save states for checkpoints and quick-saves
+    handle.add_class::<save_state::SaveStates>();
 
     init_panic_hook();
 }
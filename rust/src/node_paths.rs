@@ -0,0 +1,42 @@
+use gdnative::prelude::*;
+
+// Fetches the native class instance at `path` during `_ready`.
+// Reports a readable error naming the node, path and expected class
+// when the node is missing or has a different script attached
+pub fn fetch_instance<T>(
+    owner: &Node,
+    path: &NodePath,
+    class_name: &str,
+) -> Option<TInstance<'static, T, Shared>>
+where
+    T: NativeClass,
+    T::Base: SubClass<Node>,
+{
+    if owner.get_node(path.new_ref()).is_none() {
+        godot_error!(
+            "{}: No node found at \"{}\", expected a {class_name}",
+            owner.name(),
+            path.to_godot_string()
+        );
+        return None;
+    }
+
+    let instance = unsafe { owner.get_node_as_instance::<T>(path.new_ref()) };
+    if instance.is_none() {
+        godot_error!(
+            "{}: Node at \"{}\" is not a {class_name}",
+            owner.name(),
+            path.to_godot_string()
+        );
+    }
+    instance
+}
+
+// Stops `_process` when any node needed by `owner` wasn't `found`, so it never
+// unwraps them. `fetch_instance` has already reported which nodes are missing
+pub fn require(owner: &Node, found: bool) {
+    if !found {
+        godot_error!("{}: Disabled until the missing nodes are set", owner.name());
+        owner.set_process(false);
+    }
+}
@@ -1,26 +1,115 @@
 use gdnative::api::Node2D;
-use gdnative::api::OS;
 use gdnative::prelude::*;
 
+use std::collections::HashMap;
+
 use crate::bullet_manager::BulletManager;
+use crate::bullet_motion::PlayerHitbox;
+use crate::node_paths;
+use crate::player_options::PlayerOptions;
+use crate::save_state::{self, PlayerSnapshot};
+use crate::ship::{BombType, Ship, Weapon};
+use crate::signals;
+use crate::time_scale::{self, TimeScale};
+
+// Fetches player 1 and, unless `second_path` is empty, player 2.
+// None when any of them is missing, the error having been reported
+pub fn fetch_players(
+    owner: &Node,
+    first_path: &NodePath,
+    second_path: &NodePath,
+) -> Option<Vec<TInstance<'static, Player, Shared>>> {
+    let mut players = vec![node_paths::fetch_instance(owner, first_path, "Player")?];
+    if !second_path.is_empty() {
+        players.push(node_paths::fetch_instance(owner, second_path, "Player")?);
+    }
+    Some(players)
+}
+
+// The players still in the game along with their hitboxes, in the same order.
+// Dead players are left out, so they are neither hit, targeted nor collecting items
+pub fn live_players(
+    players: &[TInstance<'static, Player, Shared>],
+) -> (Vec<TInstance<'static, Player, Shared>>, Vec<PlayerHitbox>) {
+    players
+        .iter()
+        .filter_map(|player| {
+            player
+                .map(|x: &Player, node: TRef<Node2D>| {
+                    let hitbox = PlayerHitbox {
+                        position: node.global_position(),
+                        radius: x.hitbox_radius(),
+                    };
+                    (!x.dead).then(|| (player.clone(), hitbox))
+                })
+                .unwrap()
+        })
+        .unzip()
+}
 
 #[derive(NativeClass, Default)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct Player {
-    #[property(default = 120)]
-    pub speed: u32,
-    #[property(default = 90)]
-    shoot_timeout_ms: u32,
-    #[property(default = 300.0)]
-    primary_speed: f32,
+    // Ship definition to play with, see Ship::from_name
+    #[property]
+    ship: String,
+    // Prepended to the name of every input action, "p2_" for player 2
+    #[property]
+    input_prefix: String,
     #[property(default = 1000)]
     hit_invulnerability_ms: i64,
+    // Score awarded for each collected score item
+    #[property(default = 10)]
+    score_item_value: i64,
+    // Lives remaining, the player dies when hit without any left
+    #[property(default = 3)]
+    lives: i64,
+    // Bombs remaining, see `bomb`
+    #[property(default = 3)]
+    bombs: i64,
+    // Maximum power reached by collecting score items
+    #[property(default = 128)]
+    max_power: i64,
+    // Power at the start of the run, used by practice mode
+    #[property(default = 0)]
+    starting_power: i64,
 
+    // Bullet manager used to fire bullets
+    #[property]
+    bullet_manager_path: NodePath,
     bullet_manager: Option<TInstance<'static, BulletManager, Shared>>,
+    // Time scale applied to the player's deltatime
+    #[property]
+    time_scale_path: NodePath,
+    time_scale: Option<TInstance<'static, TimeScale, Shared>>,
+    // Options following the player, empty for none
+    #[property]
+    options_path: NodePath,
+    options: Option<TInstance<'static, PlayerOptions, Shared>>,
+
+    ship_type: Ship,
+    // Scene instanced as a child of the player for the sprite of each ship,
+    // set through the ship_sprites/ properties
+    ship_sprites: HashMap<Ship, Ref<PackedScene, Shared>>,
+    // Where the player started, practice jumps put it back there
+    start_position: Vector2,
+    // (power, lives, bombs) at the start of the run, see set_loadout
+    loadout: (i64, i64, i64),
 
+    // Simulation time (msec), timers below are based on it
+    time: f32,
     last_attack: i64,
     last_hit: i64, // -1 for never
 
+    // Hit without any lives left, `died` has been emitted
+    dead: bool,
+
+    score: i64,
+    power: i64,
+    // Enemy bullets that passed close by without hitting
+    graze: i64,
+
     invulnerability_anim: u8,
 }
 
@@ -28,119 +117,396 @@ pub struct Player {
 impl Player {
     fn new(_owner: &Node2D) -> Self {
         Self {
-            speed: 120,
-            shoot_timeout_ms: 90,
-            primary_speed: 300.0,
+            ship: Ship::default().name().to_string(),
+            ship_type: Ship::default(),
+            ship_sprites: HashMap::new(),
+            bullet_manager_path: NodePath::from_str("../Bullets"),
             bullet_manager: None,
+            time_scale_path: NodePath::from_str("../Time"),
+            time_scale: None,
+            start_position: Vector2::new(0.0, 0.0),
+            loadout: (0, 3, 3),
+            options_path: NodePath::from_str("Options"),
+            options: None,
             hit_invulnerability_ms: 1000,
+            score_item_value: 10,
+            lives: 3,
+            bombs: 3,
+            max_power: 128,
+            starting_power: 0,
 
+            time: 0.0,
             last_attack: 0,
             last_hit: -1,
 
+            dead: false,
+
+            score: 0,
+            power: 0,
+            graze: 0,
+
             invulnerability_anim: 0,
         }
     }
 
+    // Signals for the HUD, audio and effects, emitted at the end of the frame
+    fn register(builder: &ClassBuilder<Self>) {
+        for ship in Ship::ALL {
+            builder
+                .property::<Option<Ref<PackedScene, Shared>>>(&format!(
+                    "ship_sprites/{}",
+                    ship.name()
+                ))
+                .with_getter(move |this: &Player, _owner: TRef<Node2D>| {
+                    this.ship_sprites.get(&ship).cloned()
+                })
+                .with_setter(move |this: &mut Player, _owner: TRef<Node2D>, v| match v {
+                    Some(scene) => {
+                        this.ship_sprites.insert(ship, scene);
+                    }
+                    None => {
+                        this.ship_sprites.remove(&ship);
+                    }
+                })
+                .done();
+        }
+
+        builder
+            .signal("hit")
+            .with_param("lives", VariantType::I64)
+            .done();
+        builder.signal("died").done();
+        builder
+            .signal("power_changed")
+            .with_param("power", VariantType::I64)
+            .done();
+    }
+
     // Called when bullet hits the player's hitbox
     // Returning true deletes the bullet, Returning false persists it
     #[export]
-    pub fn hit(&mut self, _owner: &Node2D) -> bool {
-        if OS::godot_singleton().get_ticks_msec() - self.last_hit > self.hit_invulnerability_ms {
-            self.last_hit = OS::godot_singleton().get_ticks_msec();
+    pub fn hit(&mut self, owner: &Node2D) -> bool {
+        // Dead players are out of the game and can't be hit again
+        if self.dead {
+            return false;
+        }
+        let now = self.time as i64;
+        if self.last_hit == -1 || now - self.last_hit > self.hit_invulnerability_ms {
+            self.last_hit = now;
+
+            if self.lives > 0 {
+                self.lives -= 1;
+                signals::emit_deferred(owner, "hit", &[self.lives.to_variant()]);
+            } else {
+                // Out of the game until practice mode restarts it, see set_loadout
+                self.dead = true;
+                owner.set_visible(false);
+                signals::emit_deferred(owner, "died", &[]);
+            }
             true
         } else {
             false
         }
     }
 
+    // Called when a score item touches the player's hitbox
+    #[export]
+    pub fn collect_item(&mut self, owner: &Node2D) {
+        self.score += self.score_item_value;
+
+        if self.power < self.max_power {
+            self.power += 1;
+            signals::emit_deferred(owner, "power_changed", &[self.power.to_variant()]);
+        }
+    }
+
+    // Called when an enemy bullet passes close to the player's hitbox
+    #[export]
+    pub fn graze(&mut self, _owner: &Node2D) {
+        self.graze += 1;
+    }
+
+    #[export]
+    pub fn score(&self, _owner: &Node2D) -> i64 {
+        self.score
+    }
+
+    #[export]
+    pub fn lives(&self, _owner: &Node2D) -> i64 {
+        self.lives
+    }
+
+    #[export]
+    pub fn is_dead(&self, _owner: &Node2D) -> bool {
+        self.dead
+    }
+
+    #[export]
+    pub fn power(&self, _owner: &Node2D) -> i64 {
+        self.power
+    }
+
+    #[export]
+    pub fn bombs(&self, _owner: &Node2D) -> i64 {
+        self.bombs
+    }
+
+    #[export]
+    pub fn graze_count(&self, _owner: &Node2D) -> i64 {
+        self.graze
+    }
+
+    // Practice mode, replaces the player's resources.
+    // Kept as the loadout given back by `restart`
+    #[export]
+    pub fn set_loadout(&mut self, owner: &Node2D, power: i64, lives: i64, bombs: i64) {
+        self.loadout = (power.clamp(0, self.max_power), lives.max(0), bombs.max(0));
+        self.power = self.loadout.0;
+        self.lives = self.loadout.1;
+        self.bombs = self.loadout.2;
+        self.dead = false;
+        owner.set_visible(true);
+        signals::emit_deferred(owner, "power_changed", &[self.power.to_variant()]);
+    }
+
+    // Practice mode, puts the player back at its start with the loadout
+    // and without any score or invulnerability, so every jump plays out the same
+    pub fn restart(&mut self, owner: &Node2D) {
+        owner.set_global_position(self.start_position);
+        owner.set_visible(true);
+        self.time = 0.0;
+        self.last_attack = 0;
+        self.last_hit = -1;
+        self.invulnerability_anim = 0;
+        self.dead = false;
+        self.score = 0;
+        self.graze = 0;
+        let (power, lives, bombs) = self.loadout;
+        self.set_loadout(owner, power, lives, bombs);
+        if let Some(options) = &self.options {
+            options
+                .map_mut(|x: &mut PlayerOptions, _| x.reset(self.start_position))
+                .unwrap();
+        }
+    }
+
+    // Cancels every enemy bullet, into score items or not depending on the ship's
+    // bomb type, and grants invulnerability. Returns false without any bombs left
+    #[export]
+    pub fn bomb(&mut self, _owner: &Node2D) -> bool {
+        if self.dead || self.bombs <= 0 {
+            return false;
+        }
+        self.bombs -= 1;
+        self.last_hit = self.time as i64;
+        let to_items = self.ship_type.definition().bomb == BombType::Items;
+        self.bullet_manager
+            .as_ref()
+            .unwrap()
+            .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                x.cancel_enemy_bullets(node.as_ref(), to_items)
+            })
+            .unwrap();
+        true
+    }
+
+    // Ship being played, recorded with scores and save states
+    pub fn ship(&self) -> Ship {
+        self.ship_type
+    }
+
+    pub fn hitbox_radius(&self) -> f32 {
+        self.ship_type.definition().hitbox_radius
+    }
+
+    pub fn snapshot(&self, owner: &Node2D) -> PlayerSnapshot {
+        PlayerSnapshot {
+            ship: self.ship_type,
+            position: save_state::to_position(owner.global_position()),
+            time: self.time,
+            last_attack: self.last_attack,
+            last_hit: self.last_hit,
+            dead: self.dead,
+            lives: self.lives,
+            bombs: self.bombs,
+            score: self.score,
+            power: self.power,
+            graze: self.graze,
+            invulnerability_anim: self.invulnerability_anim,
+            options: self
+                .options
+                .as_ref()
+                .map(|x| x.map(|x: &PlayerOptions, _| x.snapshot()).unwrap()),
+        }
+    }
+
+    pub fn restore(&mut self, owner: &Node2D, snapshot: &PlayerSnapshot) {
+        owner.set_global_position(save_state::from_position(snapshot.position));
+        self.time = snapshot.time;
+        self.last_attack = snapshot.last_attack;
+        self.last_hit = snapshot.last_hit;
+        self.dead = snapshot.dead;
+        self.lives = snapshot.lives;
+        self.bombs = snapshot.bombs;
+        self.score = snapshot.score;
+        self.power = snapshot.power;
+        self.graze = snapshot.graze;
+        self.invulnerability_anim = snapshot.invulnerability_anim;
+        owner.set_visible(!self.dead && self.invulnerability_anim <= 1);
+        if let (Some(options), Some(saved)) = (&self.options, &snapshot.options) {
+            options
+                .map_mut(|x: &mut PlayerOptions, _| x.restore(saved))
+                .unwrap();
+        }
+    }
+
+    // Name of this player's input action
+    fn action(&self, name: &str) -> String {
+        format!("{}{name}", self.input_prefix)
+    }
+
+    // Instances the ship's sprite scene as a child of the player
+    fn add_ship_sprite(&self, owner: &Node2D) {
+        let name = self.ship_type.name();
+        let scene = match self.ship_sprites.get(&self.ship_type) {
+            Some(scene) => unsafe { scene.assume_safe() },
+            None => {
+                godot_warn!("No ship_sprites/{name} set, the player has no sprite");
+                return;
+            }
+        };
+        match scene.instance(PackedScene::GEN_EDIT_STATE_DISABLED) {
+            Some(sprite) => owner.add_child(sprite, false),
+            None => godot_error!("Could not instance ship_sprites/{name}"),
+        }
+    }
+
+    // Spawns one volley of the weapon
+    fn fire(&self, pos: Vector2, weapon: &Weapon) {
+        self.bullet_manager
+            .as_ref()
+            .unwrap()
+            .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                for shot in weapon.shots {
+                    x.spawn(
+                        node.as_ref(),
+                        shot.kind,
+                        pos + Vector2::new(shot.offset.0, shot.offset.1),
+                        Vector2::new(shot.dx, -weapon.speed),
+                    );
+                }
+            })
+            .unwrap();
+    }
+
     // Called when the game is ready to start
     #[export]
     fn _ready(&mut self, owner: &Node2D) {
+        self.ship_type = Ship::from_name(&self.ship).unwrap_or_else(|| {
+            godot_warn!(
+                "Unknown ship \"{}\", using \"{}\"",
+                self.ship,
+                Ship::default().name()
+            );
+            Ship::default()
+        });
+        self.add_ship_sprite(owner);
+
         // Store a copy of the bullet manager in order to shoot bullets
-        self.bullet_manager = unsafe { owner.get_node_as_instance::<BulletManager>("../Bullets") };
+        self.bullet_manager =
+            node_paths::fetch_instance(owner, &self.bullet_manager_path, "BulletManager");
+        node_paths::require(owner, self.bullet_manager.is_some());
+        self.time_scale = node_paths::fetch_instance(owner, &self.time_scale_path, "TimeScale");
+        if !self.options_path.is_empty() {
+            self.options = node_paths::fetch_instance(owner, &self.options_path, "PlayerOptions");
+        }
+        self.power = self.starting_power.clamp(0, self.max_power);
+        self.start_position = owner.global_position();
+        self.loadout = (self.power, self.lives, self.bombs);
     }
 
     #[export]
     fn _process(&mut self, owner: &Node2D, delta: f32) {
+        // Freeze entirely (including input) while paused or in a hit-stop
+        let delta = time_scale::scaled(&self.time_scale, delta);
+        if delta == 0.0 {
+            return;
+        }
+        self.time += delta * 1000.0;
+        // Dead players neither move nor shoot
+        if self.dead {
+            return;
+        }
+
         let input = Input::godot_singleton();
 
         // Calculate the position change for the tick
         let mut velocity = Vector2::new(0.0, 0.0);
-        if Input::is_action_pressed(input, "move_up", false) {
+        if Input::is_action_pressed(input, self.action("move_up"), false) {
             velocity.y -= 1.0;
         }
-        if Input::is_action_pressed(input, "move_down", false) {
+        if Input::is_action_pressed(input, self.action("move_down"), false) {
             velocity.y += 1.0;
         }
-        if Input::is_action_pressed(input, "move_left", false) {
+        if Input::is_action_pressed(input, self.action("move_left"), false) {
             velocity.x -= 1.0;
         }
-        if Input::is_action_pressed(input, "move_right", false) {
+        if Input::is_action_pressed(input, self.action("move_right"), false) {
             velocity.x += 1.0;
         }
 
-        let change = velocity * self.speed as f32 * delta;
+        let ship = self.ship_type.definition();
+        let focused = Input::is_action_pressed(input, self.action("focus"), false);
+        let speed = if focused {
+            ship.focus_speed
+        } else {
+            ship.speed
+        };
+        let change = velocity * speed * delta;
         owner.set_global_position(owner.global_position() + change);
 
-        let now = OS::godot_singleton().get_ticks_msec();
+        let now = self.time as i64;
 
-        // Manage firing of bullets
-        if Input::is_action_pressed(input, "shoot_1", false)
-            && (now - self.last_attack) > self.shoot_timeout_ms as i64
+        // Manage firing of bullets, focusing switches to the secondary weapon
+        let weapon = if focused {
+            &ship.secondary
+        } else {
+            &ship.primary
+        };
+        if Input::is_action_pressed(input, self.action("shoot_1"), false)
+            && (now - self.last_attack) > weapon.timeout_ms
         {
             self.last_attack = now;
+            self.fire(owner.global_position(), weapon);
+        }
+
+        // Options follow the player and shoot alongside the primary fire
+        if let Some(options) = &self.options {
+            let bullet_manager = self.bullet_manager.as_ref().unwrap();
             let pos = owner.global_position();
-            self.bullet_manager
-                .as_ref()
-                .unwrap()
-                .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
-                    // Spawn the set of bullets for the primary fire
-                    x.spawn_bullet(
-                        &*node,
-                        "player_primary_03".to_string(),
-                        pos.x - 12.0,
-                        pos.y - 6.0,
-                        -20.0,
-                        -self.primary_speed,
-                    );
-                    x.spawn_bullet(
-                        &*node,
-                        "player_primary_02".to_string(),
-                        pos.x - 10.0,
-                        pos.y - 9.0,
-                        -10.0,
-                        -self.primary_speed,
-                    );
-                    x.spawn_bullet(
-                        &*node,
-                        "player_primary_01".to_string(),
-                        pos.x - 4.0,
-                        pos.y - 19.0,
-                        0.0,
-                        -self.primary_speed,
-                    );
-                    x.spawn_bullet(
-                        &*node,
-                        "player_primary_02".to_string(),
-                        pos.x + 5.0,
-                        pos.y - 9.0,
-                        10.0,
-                        -self.primary_speed,
-                    );
-                    x.spawn_bullet(
-                        &*node,
-                        "player_primary_03".to_string(),
-                        pos.x + 11.0,
-                        pos.y - 6.0,
-                        20.0,
-                        -self.primary_speed,
-                    );
+            let shooting = Input::is_action_pressed(input, self.action("shoot_1"), false);
+            options
+                .map_mut(|x: &mut PlayerOptions, node: TRef<Node2D>| {
+                    x.tick(
+                        node.as_ref(),
+                        bullet_manager,
+                        pos,
+                        self.power,
+                        focused,
+                        shooting,
+                        now,
+                        delta,
+                    )
                 })
                 .unwrap();
         }
 
+        if Input::is_action_just_pressed(input, self.action("shoot_2"), false) {
+            self.bomb(owner);
+        }
+
         // Animate invulnerability with toggling visibility
-        if OS::godot_singleton().get_ticks_msec() - self.last_hit <= self.hit_invulnerability_ms {
+        if self.last_hit != -1 && now - self.last_hit <= self.hit_invulnerability_ms {
             self.invulnerability_anim = (self.invulnerability_anim + 1) % 4;
             if self.invulnerability_anim <= 1 {
                 owner.set_visible(true);
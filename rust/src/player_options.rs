@@ -0,0 +1,341 @@
+use gdnative::api::Node2D;
+use gdnative::prelude::*;
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::bullet_manager::BulletManager;
+use crate::save_state::{self, OptionsSnapshot};
+
+// How options are placed around the player while not focused
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Formation {
+    // At fixed offsets from the player, see `offsets`
+    #[default]
+    Fixed,
+    // Following the path the player moved along
+    Trail,
+}
+
+impl Formation {
+    pub fn from_name(name: &str) -> Option<Formation> {
+        match name {
+            "fixed" => Some(Formation::Fixed),
+            "trail" => Some(Formation::Trail),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Formation::Fixed => "fixed",
+            Formation::Trail => "trail",
+        }
+    }
+}
+
+// Where option `index` of `count` circles the player while focused,
+// the first one being at `angle` (radians)
+fn circle_position(
+    player_pos: Vector2,
+    angle: f32,
+    radius: f32,
+    index: usize,
+    count: usize,
+) -> Vector2 {
+    let angle = angle + 2.0 * PI * index as f32 / count as f32;
+    player_pos + Vector2::new(angle.cos(), angle.sin()) * radius
+}
+
+// Where option `index` follows the player in the "trail" formation, `delay` positions
+// apart along the trail, or at its end while the player hasn't moved far enough
+fn trail_position(
+    trail: &VecDeque<Vector2>,
+    delay: u32,
+    index: usize,
+    player_pos: Vector2,
+) -> Vector2 {
+    let delay = (index + 1) * delay.max(1) as usize;
+    match trail.get(delay).or_else(|| trail.back()) {
+        Some(pos) => *pos,
+        None => player_pos,
+    }
+}
+
+// Satellite units of the player, gained with power, which fire their own
+// (optionally homing) shots. Ticked by the Player it is a child of
+#[derive(NativeClass, Default)]
+#[inherit(Node2D)]
+pub struct PlayerOptions {
+    // Scene instanced for each option
+    #[property]
+    option_scene: Option<Ref<PackedScene, Shared>>,
+    #[property(default = 4)]
+    max_options: u32,
+    // Power needed for each option
+    #[property(default = 32)]
+    power_per_option: i64,
+
+    // "fixed" or "trail"
+    #[property]
+    formation: String,
+    formation_type: Formation,
+    // Offset from the player of each option in the "fixed" formation
+    #[property]
+    offsets: Vector2Array,
+    // Ticks of player movement between each option in the "trail" formation
+    #[property(default = 8)]
+    trail_delay: u32,
+    // While focused the options circle the player instead
+    #[property(default = 24.0)]
+    focus_radius: f32,
+    // (radians/sec)
+    #[property(default = 3.0)]
+    focus_rotation_speed: f32,
+    // Speed options move at towards their place in the formation (px/sec)
+    #[property(default = 360.0)]
+    move_speed: f32,
+
+    #[property(default = 150)]
+    shoot_timeout_ms: u32,
+    #[property]
+    shot_kind: String,
+    #[property(default = 300.0)]
+    shot_speed: f32,
+    // Rate shots turn towards the nearest enemy (radians/sec)
+    // 0 = straight shots
+    #[property(default = 4.0)]
+    homing_turn_rate: f32,
+
+    nodes: Vec<Ref<Node2D, Shared>>,
+    positions: Vec<Vector2>,
+    // Positions the player moved through, most recent first
+    trail: VecDeque<Vector2>,
+    // Current direction of the first option while focused (radians)
+    angle: f32,
+    last_attack: i64,
+}
+
+#[methods]
+impl PlayerOptions {
+    fn new(_owner: &Node2D) -> Self {
+        Self {
+            max_options: 4,
+            power_per_option: 32,
+            formation: Formation::default().name().to_string(),
+            offsets: Vector2Array::from_vec(vec![
+                Vector2::new(-20.0, 4.0),
+                Vector2::new(20.0, 4.0),
+                Vector2::new(-34.0, 12.0),
+                Vector2::new(34.0, 12.0),
+            ]),
+            trail_delay: 8,
+            focus_radius: 24.0,
+            focus_rotation_speed: 3.0,
+            move_speed: 360.0,
+            shoot_timeout_ms: 150,
+            shot_kind: "player_primary_01".to_string(),
+            shot_speed: 300.0,
+            homing_turn_rate: 4.0,
+            ..Default::default()
+        }
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Node2D) {
+        self.formation_type = Formation::from_name(&self.formation).unwrap_or_else(|| {
+            godot_warn!(
+                "Unknown formation \"{}\", using \"{}\"",
+                self.formation,
+                Formation::default().name()
+            );
+            Formation::default()
+        });
+
+        let scene = match &self.option_scene {
+            Some(scene) => unsafe { scene.assume_safe() },
+            None => {
+                godot_warn!("No option_scene set, the player has no options");
+                return;
+            }
+        };
+        let start = owner.global_position();
+        for _ in 0..self.max_options {
+            let node = scene
+                .instance(PackedScene::GEN_EDIT_STATE_DISABLED)
+                .and_then(|x| unsafe { x.assume_unique() }.cast::<Node2D>());
+            let node = match node {
+                Some(node) => node,
+                None => {
+                    godot_warn!("option_scene must have a Node2D root");
+                    return;
+                }
+            };
+            // Placed in global coordinates, not following the player
+            node.set_as_toplevel(true);
+            node.set_visible(false);
+            let node = node.into_shared();
+            owner.add_child(node, false);
+            self.nodes.push(node);
+            self.positions.push(start);
+        }
+    }
+
+    // Options available at the given power
+    fn count(&self, power: i64) -> usize {
+        let count = power / self.power_per_option.max(1);
+        (count.max(0) as usize).min(self.nodes.len())
+    }
+
+    // Where option `index` of `count` belongs in the formation
+    fn target(&self, index: usize, count: usize, player_pos: Vector2, focused: bool) -> Vector2 {
+        if focused {
+            return circle_position(player_pos, self.angle, self.focus_radius, index, count);
+        }
+        match self.formation_type {
+            Formation::Fixed => {
+                let offsets = self.offsets.read();
+                player_pos + offsets.get(index).copied().unwrap_or(Vector2::new(0.0, 0.0))
+            }
+            Formation::Trail => trail_position(&self.trail, self.trail_delay, index, player_pos),
+        }
+    }
+
+    // Moves the options with the player and fires their shots while `shooting`.
+    // `now` is the simulation time of the player (msec)
+    #[allow(clippy::too_many_arguments)]
+    pub fn tick(
+        &mut self,
+        _owner: &Node2D,
+        bullet_manager: &TInstance<'static, BulletManager, Shared>,
+        player_pos: Vector2,
+        power: i64,
+        focused: bool,
+        shooting: bool,
+        now: i64,
+        deltatime: f32,
+    ) {
+        // The trail only grows while the player moves, so options bunch up when still
+        if self.trail.front() != Some(&player_pos) {
+            self.trail.push_front(player_pos);
+            let length = self.nodes.len() * self.trail_delay.max(1) as usize + 1;
+            self.trail.truncate(length);
+        }
+        if focused {
+            self.angle = (self.angle + self.focus_rotation_speed * deltatime) % (2.0 * PI);
+        }
+
+        let count = self.count(power);
+        for i in 0..self.nodes.len() {
+            let node = unsafe { self.nodes[i].assume_safe() };
+            if i >= count {
+                // Options gained later appear from the player
+                self.positions[i] = player_pos;
+                node.set_visible(false);
+                continue;
+            }
+            let target = self.target(i, count, player_pos, focused);
+            let offset = target - self.positions[i];
+            let step = self.move_speed * deltatime;
+            if offset.length() <= step {
+                self.positions[i] = target;
+            } else {
+                self.positions[i] += offset.normalized() * step;
+            }
+            node.set_global_position(self.positions[i]);
+            node.set_visible(true);
+        }
+
+        if !shooting || count == 0 || now - self.last_attack <= self.shoot_timeout_ms as i64 {
+            return;
+        }
+        self.last_attack = now;
+        let positions = &self.positions[..count];
+        let kind = &self.shot_kind;
+        let speed = self.shot_speed;
+        let turn_rate = self.homing_turn_rate;
+        bullet_manager
+            .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                for pos in positions {
+                    let velocity = Vector2::new(0.0, -speed);
+                    if let Some(handle) = x.spawn(node.as_ref(), kind, *pos, velocity) {
+                        if turn_rate > 0.0 {
+                            x.set_homing(handle, Some(turn_rate));
+                        }
+                    }
+                }
+            })
+            .unwrap();
+    }
+
+    // Gathers every option back on the player, as at the start of the run
+    pub fn reset(&mut self, player_pos: Vector2) {
+        for pos in self.positions.iter_mut() {
+            *pos = player_pos;
+        }
+        self.trail.clear();
+        self.angle = 0.0;
+        self.last_attack = 0;
+    }
+
+    pub fn snapshot(&self) -> OptionsSnapshot {
+        OptionsSnapshot {
+            positions: self
+                .positions
+                .iter()
+                .map(|x| save_state::to_position(*x))
+                .collect(),
+            trail: self.trail.iter().map(|x| save_state::to_position(*x)).collect(),
+            angle: self.angle,
+            last_attack: self.last_attack,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &OptionsSnapshot) {
+        for (pos, saved) in self.positions.iter_mut().zip(&snapshot.positions) {
+            *pos = save_state::from_position(*saved);
+        }
+        self.trail = snapshot.trail.iter().map(|x| save_state::from_position(*x)).collect();
+        self.angle = snapshot.angle;
+        self.last_attack = snapshot.last_attack;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focused_options_circle_the_player() {
+        let player = Vector2::new(100.0, 200.0);
+        // Spread evenly around the player, starting at the current angle
+        let cases = [
+            (0.0, 0, 4, Vector2::new(124.0, 200.0)),
+            (0.0, 1, 4, Vector2::new(100.0, 224.0)),
+            (0.0, 2, 4, Vector2::new(76.0, 200.0)),
+            (0.0, 3, 4, Vector2::new(100.0, 176.0)),
+            (PI / 2.0, 0, 2, Vector2::new(100.0, 224.0)),
+            (PI / 2.0, 1, 2, Vector2::new(100.0, 176.0)),
+        ];
+        for (angle, index, count, expected) in cases {
+            let found = circle_position(player, angle, 24.0, index, count);
+            assert!(found.distance_to(expected) < 0.001, "{found:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn trail_options_follow_the_player() {
+        let player = Vector2::new(0.0, 0.0);
+        // Most recent first
+        let trail: VecDeque<Vector2> = (0..10).map(|x| Vector2::new(x as f32, 0.0)).collect();
+        assert_eq!(trail_position(&trail, 3, 0, player), Vector2::new(3.0, 0.0));
+        assert_eq!(trail_position(&trail, 3, 1, player), Vector2::new(6.0, 0.0));
+        assert_eq!(trail_position(&trail, 3, 2, player), Vector2::new(9.0, 0.0));
+        // Bunched up at the end of a short trail
+        assert_eq!(trail_position(&trail, 3, 3, player), Vector2::new(9.0, 0.0));
+        // A delay of 0 is treated as 1
+        assert_eq!(trail_position(&trail, 0, 0, player), Vector2::new(1.0, 0.0));
+        // On the player before it has moved
+        assert_eq!(trail_position(&VecDeque::new(), 3, 0, player), player);
+    }
+}
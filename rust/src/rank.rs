@@ -0,0 +1,355 @@
+use gdnative::export::PropertyUsage;
+use gdnative::prelude::*;
+
+use crate::difficulty::DifficultyProfile;
+use crate::encounter_manager::{self, EncounterManager};
+use crate::node_paths;
+use crate::player::Player;
+use crate::save_state::RankSnapshot;
+use crate::time_scale::{self, TimeScale};
+
+// Multipliers from the current rank, applied on top of the difficulty profile
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RankScale {
+    pub bullet_speed: f32,
+    pub fire_rate: f32,
+}
+
+impl Default for RankScale {
+    fn default() -> Self {
+        Self {
+            bullet_speed: 1.0,
+            fire_rate: 1.0,
+        }
+    }
+}
+
+impl RankScale {
+    pub fn apply(&self, profile: DifficultyProfile) -> DifficultyProfile {
+        DifficultyProfile {
+            bullet_speed: profile.bullet_speed * self.bullet_speed,
+            fire_rate: profile.fire_rate * self.fire_rate,
+            ..profile
+        }
+    }
+}
+
+// Dynamic rank (0.0 - 1.0) rising as the player does well and dropping when they
+// are hit or bomb, making enemy bullets faster and more frequent at high rank
+#[derive(NativeClass, Default)]
+#[inherit(Node)]
+#[register_with(Self::register)]
+pub struct Rank {
+    // Rank stays at 0, leaving enemies unchanged, while disabled
+    #[property(default = false)]
+    enabled: bool,
+    #[property(default = 0.0)]
+    starting_rank: f32,
+
+    // Increase per second survived without being hit
+    #[property(default = 0.005)]
+    survival_rate: f32,
+    // Increase per point of score gained
+    #[property(default = 0.00002)]
+    score_rate: f32,
+    // Increase per point of power gained
+    #[property(default = 0.002)]
+    power_rate: f32,
+    // Decrease when the player is hit
+    #[property(default = 0.2)]
+    hit_penalty: f32,
+    // Decrease when the player bombs
+    #[property(default = 0.1)]
+    bomb_penalty: f32,
+
+    // Multipliers at rank 1.0, rank 0.0 leaves enemies unchanged
+    #[property(default = 1.5)]
+    max_bullet_speed: f32,
+    #[property(default = 1.5)]
+    max_fire_rate: f32,
+
+    // Change in rank before it is logged again
+    #[property(default = 0.05)]
+    log_step: f32,
+
+    // Provides the players, player 1's performance sets the rank in co-op
+    #[property]
+    encounter_manager_path: NodePath,
+    player: Option<TInstance<'static, Player, Shared>>,
+    #[property]
+    time_scale_path: NodePath,
+    time_scale: Option<TInstance<'static, TimeScale, Shared>>,
+
+    rank: f32,
+    // Rank when it was last logged
+    logged_rank: f32,
+    // Player values from the last tick, used to find what changed
+    started: bool,
+    last: PlayerValues,
+}
+
+// What the rank follows of the player on each tick
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PlayerValues {
+    pub score: i64,
+    pub power: i64,
+    pub lives: i64,
+    pub bombs: i64,
+    pub dead: bool,
+}
+
+// How the rank changes with the player's performance, see the Rank properties of the same name
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RankRates {
+    pub survival_rate: f32,
+    pub score_rate: f32,
+    pub power_rate: f32,
+    pub hit_penalty: f32,
+    pub bomb_penalty: f32,
+}
+
+impl RankRates {
+    // Change in rank as the player went from the last values to the current ones,
+    // along with its reason
+    pub fn change(
+        &self,
+        last: PlayerValues,
+        values: PlayerValues,
+        deltatime: f32,
+    ) -> (f32, &'static str) {
+        let mut change = deltatime * self.survival_rate
+            + (values.score - last.score).max(0) as f32 * self.score_rate
+            + (values.power - last.power).max(0) as f32 * self.power_rate;
+        let mut reason = "performance";
+        // Losing the last life leaves the lives unchanged, only marking the player dead
+        if values.lives < last.lives || (values.dead && !last.dead) {
+            change -= self.hit_penalty;
+            reason = "hit";
+        }
+        if values.bombs < last.bombs {
+            change -= self.bomb_penalty;
+            reason = "bomb";
+        }
+        (change, reason)
+    }
+
+    // Rank once the player went from the last values to the current ones, kept within 0.0 - 1.0
+    pub fn next_rank(
+        &self,
+        rank: f32,
+        last: PlayerValues,
+        values: PlayerValues,
+        deltatime: f32,
+    ) -> (f32, &'static str) {
+        let (change, reason) = self.change(last, values, deltatime);
+        ((rank + change).clamp(0.0, 1.0), reason)
+    }
+}
+
+#[methods]
+impl Rank {
+    fn new(_owner: &Node) -> Self {
+        Self {
+            survival_rate: 0.005,
+            score_rate: 0.00002,
+            power_rate: 0.002,
+            hit_penalty: 0.2,
+            bomb_penalty: 0.1,
+            max_bullet_speed: 1.5,
+            max_fire_rate: 1.5,
+            log_step: 0.05,
+            encounter_manager_path: NodePath::from_str("../Encounters"),
+            time_scale_path: NodePath::from_str("../Time"),
+            ..Default::default()
+        }
+    }
+
+    // Read-only rank for debugging overlays
+    fn register(builder: &ClassBuilder<Self>) {
+        builder
+            .property::<f32>("rank")
+            .with_usage(PropertyUsage::EDITOR)
+            .with_getter(|this: &Rank, _owner: TRef<Node>| this.rank)
+            .done();
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Node) {
+        let encounter_manager: Option<TInstance<'static, EncounterManager, Shared>> =
+            node_paths::fetch_instance(owner, &self.encounter_manager_path, "EncounterManager");
+        self.player = encounter_manager::players(&encounter_manager)
+            .unwrap_or_default()
+            .into_iter()
+            .next();
+        node_paths::require(owner, self.player.is_some());
+        self.time_scale = node_paths::fetch_instance(owner, &self.time_scale_path, "TimeScale");
+
+        self.rank = self.starting_rank.clamp(0.0, 1.0);
+        self.logged_rank = self.rank;
+    }
+
+    #[export]
+    fn _process(&mut self, _owner: &Node, deltatime: f32) {
+        let deltatime = time_scale::scaled(&self.time_scale, deltatime);
+        if !self.enabled || deltatime == 0.0 {
+            return;
+        }
+
+        let values = self.player_values();
+        if !self.started {
+            // The Player is only ready once every node is, so take its values on the first tick
+            self.started = true;
+            self.last = values;
+        }
+        let (rank, reason) = self.rates().next_rank(self.rank, self.last, values, deltatime);
+        self.last = values;
+
+        self.rank = rank;
+        if (self.rank - self.logged_rank).abs() >= self.log_step {
+            godot_print!("Rank {:.3} -> {:.3} ({reason})", self.logged_rank, self.rank);
+            self.logged_rank = self.rank;
+        }
+    }
+
+    fn rates(&self) -> RankRates {
+        RankRates {
+            survival_rate: self.survival_rate,
+            score_rate: self.score_rate,
+            power_rate: self.power_rate,
+            hit_penalty: self.hit_penalty,
+            bomb_penalty: self.bomb_penalty,
+        }
+    }
+
+    // Multipliers for enemy patterns at the current rank
+    pub fn scale(&self) -> RankScale {
+        if !self.enabled {
+            return RankScale::default();
+        }
+        RankScale {
+            bullet_speed: 1.0 + (self.max_bullet_speed - 1.0) * self.rank,
+            fire_rate: 1.0 + (self.max_fire_rate - 1.0) * self.rank,
+        }
+    }
+
+    pub fn snapshot(&self) -> RankSnapshot {
+        RankSnapshot {
+            rank: self.rank,
+            last_score: self.last.score,
+            last_power: self.last.power,
+            last_lives: self.last.lives,
+            last_bombs: self.last.bombs,
+            last_dead: self.last.dead,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &RankSnapshot) {
+        self.started = true;
+        self.rank = snapshot.rank;
+        self.logged_rank = snapshot.rank;
+        self.last = PlayerValues {
+            score: snapshot.last_score,
+            power: snapshot.last_power,
+            lives: snapshot.last_lives,
+            bombs: snapshot.last_bombs,
+            dead: snapshot.last_dead,
+        };
+    }
+
+    fn player_values(&self) -> PlayerValues {
+        match &self.player {
+            Some(player) => player
+                .map(|x: &Player, node: TRef<Node2D>| PlayerValues {
+                    score: x.score(node.as_ref()),
+                    power: x.power(node.as_ref()),
+                    lives: x.lives(node.as_ref()),
+                    bombs: x.bombs(node.as_ref()),
+                    dead: x.is_dead(node.as_ref()),
+                })
+                .unwrap(),
+            None => PlayerValues::default(),
+        }
+    }
+}
+
+// Rank multipliers, unchanged when no Rank was found
+pub fn scale(rank: &Option<TInstance<'static, Rank, Shared>>) -> RankScale {
+    match rank {
+        Some(rank) => rank
+            .map(|x: &Rank, _node: TRef<Node>| x.scale())
+            .unwrap(),
+        None => RankScale::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: RankRates = RankRates {
+        survival_rate: 0.005,
+        score_rate: 0.00002,
+        power_rate: 0.002,
+        hit_penalty: 0.2,
+        bomb_penalty: 0.1,
+    };
+
+    const START: PlayerValues = PlayerValues {
+        score: 1000,
+        power: 10,
+        lives: 2,
+        bombs: 3,
+        dead: false,
+    };
+
+    fn assert_change(values: PlayerValues, deltatime: f32, expected: f32, reason: &str) {
+        let (change, found) = RATES.change(START, values, deltatime);
+        assert!((change - expected).abs() < 1e-6, "{change} != {expected}");
+        assert_eq!(found, reason);
+    }
+
+    #[test]
+    fn rises_with_survival_score_and_power() {
+        assert_change(START, 2.0, 0.01, "performance");
+        let values = PlayerValues {
+            score: 1500,
+            power: 12,
+            ..START
+        };
+        assert_change(values, 0.0, 0.01 + 0.004, "performance");
+        // Losing score or power doesn't lower the rank
+        let values = PlayerValues {
+            score: 0,
+            power: 0,
+            ..START
+        };
+        assert_change(values, 0.0, 0.0, "performance");
+    }
+
+    #[test]
+    fn drops_when_hit_or_bombing() {
+        assert_change(PlayerValues { lives: 1, ..START }, 0.0, -0.2, "hit");
+        assert_change(PlayerValues { bombs: 2, ..START }, 0.0, -0.1, "bomb");
+    }
+
+    #[test]
+    fn drops_on_death() {
+        // The last hit leaves the lives at 0, only marking the player dead
+        let last = PlayerValues { lives: 0, ..START };
+        let dead = PlayerValues { dead: true, ..last };
+        assert_eq!(RATES.change(last, dead, 0.0), (-0.2, "hit"));
+        // Only once
+        assert_eq!(RATES.change(dead, dead, 0.0), (0.0, "performance"));
+    }
+
+    #[test]
+    fn death_lowers_the_rank() {
+        let last = PlayerValues { lives: 0, ..START };
+        let dead = PlayerValues { dead: true, ..last };
+        let (rank, reason) = RATES.next_rank(0.5, last, dead, 0.0);
+        assert!((rank - 0.3).abs() < 1e-6);
+        assert_eq!(reason, "hit");
+        // Never below 0
+        assert_eq!(RATES.next_rank(0.1, last, dead, 0.0), (0.0, "hit"));
+    }
+}
@@ -0,0 +1,325 @@
+use gdnative::prelude::*;
+
+use crate::save_state::{RngSnapshot, SaveStateError};
+
+// PCG32 (pcg-random.org), using only integer arithmetic so a seed
+// produces the same sequence on every platform
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    // Generators with the same seed but different streams are independent
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    // Uniform in [0.0, 1.0), from the top 24 bits so every value is exact
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    // Uniform in [min, max)
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    // Uniform in [min, max], both inclusive
+    pub fn range_i64(&mut self, min: i64, max: i64) -> i64 {
+        if max <= min {
+            return min;
+        }
+        let span = (max as i128 - min as i128 + 1) as u128;
+        (min as i128 + (self.next_u64() as u128 % span) as i128) as i64
+    }
+
+    // True with the given probability (0.0 - 1.0)
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
+
+// Independent sequences, so drawing from one never shifts the others.
+// Only Cosmetic may be used for effects that don't change the simulation
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RngStream {
+    // Aimed-random spreads and other enemy patterns
+    Pattern,
+    // Item drop tables
+    Drop,
+    // Visual effects, free to vary between replays
+    Cosmetic,
+}
+
+impl RngStream {
+    // Parses the names used by exported methods
+    pub fn from_name(name: &str) -> Option<RngStream> {
+        match name {
+            "pattern" => Some(RngStream::Pattern),
+            "drop" => Some(RngStream::Drop),
+            "cosmetic" => Some(RngStream::Cosmetic),
+            _ => None,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            RngStream::Pattern => 0,
+            RngStream::Drop => 1,
+            RngStream::Cosmetic => 2,
+        }
+    }
+}
+
+// Seeded random numbers for the run, shared by every subsystem.
+// Reseeding with the seed of a run reproduces its patterns and drops
+#[derive(NativeClass)]
+#[inherit(Node)]
+pub struct Rng {
+    // Seed used at the start of the run
+    #[property(default = 0)]
+    seed: i64,
+    // Picks a new seed at the start of the run instead of using `seed`
+    #[property(default = false)]
+    randomize: bool,
+
+    streams: [Pcg32; 3],
+}
+
+#[methods]
+impl Rng {
+    fn new(_owner: &Node) -> Self {
+        Self {
+            seed: 0,
+            randomize: false,
+            streams: Rng::seeded(0),
+        }
+    }
+
+    fn seeded(seed: i64) -> [Pcg32; 3] {
+        [
+            Pcg32::new(seed as u64, RngStream::Pattern.index() as u64),
+            Pcg32::new(seed as u64, RngStream::Drop.index() as u64),
+            Pcg32::new(seed as u64, RngStream::Cosmetic.index() as u64),
+        ]
+    }
+
+    #[export]
+    fn _ready(&mut self, _owner: &Node) {
+        if self.randomize {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos() as i64)
+                .unwrap_or(0);
+            self.seed = time;
+        }
+        self.streams = Rng::seeded(self.seed);
+    }
+
+    pub fn stream(&mut self, stream: RngStream) -> &mut Pcg32 {
+        &mut self.streams[stream.index()]
+    }
+
+    // Restarts every stream from a seed
+    #[export]
+    pub fn reseed(&mut self, _owner: &Node, seed: i64) {
+        self.seed = seed;
+        self.streams = Rng::seeded(seed);
+    }
+
+    // Seed of the run, to be recorded with replays and scores
+    #[export]
+    pub fn get_seed(&self, _owner: &Node) -> i64 {
+        self.seed
+    }
+
+    // The methods below take the stream name, "pattern", "drop" or "cosmetic"
+    #[export]
+    fn randf(&mut self, _owner: &Node, stream: String) -> f32 {
+        match self.named_stream(&stream) {
+            Some(rng) => rng.next_f32(),
+            None => 0.0,
+        }
+    }
+
+    #[export]
+    fn randf_range(&mut self, _owner: &Node, stream: String, min: f32, max: f32) -> f32 {
+        match self.named_stream(&stream) {
+            Some(rng) => rng.range_f32(min, max),
+            None => min,
+        }
+    }
+
+    #[export]
+    fn randi_range(&mut self, _owner: &Node, stream: String, min: i64, max: i64) -> i64 {
+        match self.named_stream(&stream) {
+            Some(rng) => rng.range_i64(min, max),
+            None => min,
+        }
+    }
+
+    #[export]
+    fn chance(&mut self, _owner: &Node, stream: String, probability: f32) -> bool {
+        match self.named_stream(&stream) {
+            Some(rng) => rng.chance(probability),
+            None => false,
+        }
+    }
+
+    fn named_stream(&mut self, name: &str) -> Option<&mut Pcg32> {
+        match RngStream::from_name(name) {
+            Some(stream) => Some(self.stream(stream)),
+            None => {
+                godot_warn!("Unknown random stream {name}");
+                None
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> RngSnapshot {
+        RngSnapshot {
+            seed: self.seed,
+            streams: self
+                .streams
+                .iter()
+                .map(|x| (x.state, x.inc))
+                .collect(),
+        }
+    }
+
+    // Wether `snapshot` has a state for every stream, so restoring it can't fail
+    pub fn check(&self, snapshot: &RngSnapshot) -> Result<(), SaveStateError> {
+        if snapshot.streams.len() != self.streams.len() {
+            return Err(SaveStateError::Mismatch(format!(
+                "Expected {} random streams, found {}",
+                self.streams.len(),
+                snapshot.streams.len()
+            )));
+        }
+        Ok(())
+    }
+
+    // Leaves every stream unchanged on failure
+    pub fn restore(&mut self, snapshot: &RngSnapshot) -> Result<(), SaveStateError> {
+        self.check(snapshot)?;
+        self.seed = snapshot.seed;
+        for (stream, (state, inc)) in self.streams.iter_mut().zip(&snapshot.streams) {
+            stream.state = *state;
+            stream.inc = *inc;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rng(seed: i64) -> Rng {
+        Rng {
+            seed,
+            randomize: false,
+            streams: Rng::seeded(seed),
+        }
+    }
+
+    #[test]
+    fn matches_reference_outputs() {
+        // First outputs of the pcg32 demo program, seed 42 and stream 54
+        let mut rng = Pcg32::new(42, 54);
+        let expected = [
+            0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+        ];
+        for value in expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+    }
+
+    #[test]
+    fn streams_are_independent() {
+        let mut first = rng(7);
+        let mut second = rng(7);
+        first.stream(RngStream::Cosmetic).next_u32();
+        assert_eq!(
+            first.stream(RngStream::Pattern).next_u32(),
+            second.stream(RngStream::Pattern).next_u32()
+        );
+    }
+
+    #[test]
+    fn restore_continues_the_sequence() {
+        let mut original = rng(1234);
+        for _ in 0..10 {
+            original.stream(RngStream::Pattern).next_u32();
+            original.stream(RngStream::Drop).next_u32();
+        }
+        let snapshot = original.snapshot();
+
+        let mut restored = rng(0);
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.seed, 1234);
+        for stream in [RngStream::Pattern, RngStream::Drop, RngStream::Cosmetic] {
+            for _ in 0..10 {
+                assert_eq!(
+                    restored.stream(stream).next_u32(),
+                    original.stream(stream).next_u32()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn restore_rejects_other_stream_counts() {
+        let mut original = rng(1234);
+        original.stream(RngStream::Drop).next_u32();
+        let mut snapshot = original.snapshot();
+        assert_eq!(snapshot.streams.len(), 3);
+        snapshot.streams.pop();
+
+        let mut restored = rng(5);
+        let before = restored.snapshot();
+        assert!(matches!(
+            restored.restore(&snapshot),
+            Err(SaveStateError::Mismatch(_))
+        ));
+        // Nothing was restored
+        assert_eq!(restored.seed, 5);
+        assert_eq!(restored.snapshot().streams, before.streams);
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds() {
+        let mut rng = Pcg32::new(3, 0);
+        for _ in 0..1000 {
+            let x = rng.range_f32(-2.0, 5.0);
+            assert!((-2.0..5.0).contains(&x));
+            let y = rng.range_i64(-3, 3);
+            assert!((-3..=3).contains(&y));
+        }
+        assert_eq!(rng.range_i64(4, 4), 4);
+        assert_eq!(rng.range_i64(i64::MIN, i64::MIN), i64::MIN);
+    }
+}
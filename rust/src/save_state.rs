@@ -0,0 +1,667 @@
+use gdnative::api::ProjectSettings;
+use gdnative::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bullet_manager::BulletManager;
+use crate::custom_encounter::lifecycle::EncounterState;
+use crate::difficulty::Difficulty;
+use crate::encounter_manager::{self, EncounterManager};
+use crate::node_paths;
+use crate::player::Player;
+use crate::rank::Rank;
+use crate::rng::Rng;
+use crate::ship::Ship;
+use crate::time_scale::TimeScale;
+
+use std::fmt;
+
+// Bumped whenever any of the snapshot types below change,
+// older save states are rejected rather than misread
+pub const SAVE_STATE_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    // The data could not be encoded or decoded
+    Encoding(String),
+    // The data was written by a different version of the game
+    Version { found: u32, expected: u32 },
+    // The data doesn't match the nodes in the scene
+    Mismatch(String),
+    Io(String),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Encoding(error) => write!(f, "Invalid save state: {error}"),
+            SaveStateError::Version { found, expected } => write!(
+                f,
+                "Save state version {found} is not supported, expected {expected}"
+            ),
+            SaveStateError::Mismatch(error) => {
+                write!(f, "Save state doesn't match the scene: {error}")
+            }
+            SaveStateError::Io(error) => write!(f, "Could not access save state: {error}"),
+        }
+    }
+}
+
+// Positions are stored as (x, y) to keep the format independent of gdnative
+pub type Position = (f32, f32);
+
+pub fn to_position(vector: Vector2) -> Position {
+    (vector.x, vector.y)
+}
+
+pub fn from_position(position: Position) -> Vector2 {
+    Vector2::new(position.0, position.1)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    // The ship decides how the player moves and shoots, so it must match when restoring
+    pub ship: Ship,
+    pub position: Position,
+    pub time: f32,
+    pub last_attack: i64,
+    pub last_hit: i64,
+    pub dead: bool,
+    pub lives: i64,
+    pub bombs: i64,
+    pub score: i64,
+    pub power: i64,
+    pub graze: i64,
+    pub invulnerability_anim: u8,
+    // None when the player has no PlayerOptions
+    pub options: Option<OptionsSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OptionsSnapshot {
+    pub positions: Vec<Position>,
+    pub trail: Vec<Position>,
+    pub angle: f32,
+    pub last_attack: i64,
+}
+
+// A bullet in play, either live or playing its despawn animation
+#[derive(Serialize, Deserialize)]
+pub struct BulletSnapshot {
+    pub kind: String,
+    pub position: Position,
+    pub dx: f32,
+    pub dy: f32,
+    pub grazed: bool,
+    pub age: f32,
+    pub burst: Option<BurstSnapshot>,
+    pub orbit: Option<OrbitSnapshot>,
+    pub group: Option<u32>,
+    // BulletVisual, with the tint as (r, g, b, a)
+    pub tint: (f32, f32, f32, f32),
+    pub scale: f32,
+    pub frame: i64,
+    pub homing: Option<f32>,
+    // Frames of the despawn animation remaining, None for live bullets
+    pub despawn_frames: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BurstSnapshot {
+    pub frames: u32,
+    pub kind: String,
+    pub count: u32,
+    pub speed: f32,
+    pub angle: f32,
+}
+
+// Parents are stored by their index in BulletManagerSnapshot::bullets
+#[derive(Serialize, Deserialize)]
+pub struct OrbitSnapshot {
+    pub parent: u64,
+    pub radius: f32,
+    pub angle: f32,
+    pub angular_speed: f32,
+}
+
+// A laser, with the fields of its LaserConfig
+#[derive(Serialize, Deserialize)]
+pub struct LaserSnapshot {
+    pub origin: Position,
+    pub angle: f32,
+    pub length: f32,
+    pub width: f32,
+    pub warning_ms: i64,
+    pub active_ms: i64,
+    pub grow_ms: i64,
+    pub rotation_speed: f32,
+    pub wave_amplitude: f32,
+    pub wave_length: f32,
+    pub wave_speed: f32,
+    pub age: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BulletManagerSnapshot {
+    // Live bullets first, orbits only refer to those
+    pub bullets: Vec<BulletSnapshot>,
+    pub lasers: Vec<LaserSnapshot>,
+    pub next_group: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MovementSnapshot {
+    pub elapsed: f32,
+    pub progress: f32,
+    pub waypoint: u64,
+    pub wait_remaining: f32,
+    pub arrived_at: Option<f32>,
+    pub exiting: bool,
+}
+
+// State of a single enemy, read through the GenericEnemy trait
+#[derive(Serialize, Deserialize)]
+pub struct EnemySnapshot {
+    pub health: u32,
+    pub enabled: bool,
+    pub escaped: bool,
+    pub time: f32,
+    pub last_attack: i64,
+    // Rotation of the attack pattern, 0 for enemies without one
+    pub attack_offset: f32,
+    pub movement: MovementSnapshot,
+}
+
+// An enemy along with its node
+#[derive(Serialize, Deserialize)]
+pub struct EnemyNodeSnapshot {
+    pub position: Position,
+    pub visible: bool,
+    pub enemy: EnemySnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GenericEncounterSnapshot {
+    pub encounter_time: f32,
+    pub ended: bool,
+    pub in_position: bool,
+    pub enemies_killed: u32,
+    pub enemies_escaped: u32,
+    pub enemies_total: u32,
+    pub orbs: Vec<EnemyNodeSnapshot>,
+    pub small_orbs: Vec<EnemyNodeSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FirstBossSnapshot {
+    pub health: u32,
+    pub time: f32,
+}
+
+// One variant for each type in EncounterType
+#[derive(Serialize, Deserialize)]
+pub enum EncounterSnapshot {
+    GenericEncounter(GenericEncounterSnapshot),
+    FirstBoss(FirstBossSnapshot),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LifecycleSnapshot {
+    pub state: EncounterState,
+    pub time_in_state: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncounterManagerSnapshot {
+    // Enemies were scaled by the difficulty, so it must match when restoring
+    pub difficulty: Difficulty,
+    pub stage_time: f32,
+    pub stage_cleared: bool,
+    pub lifecycles: Vec<LifecycleSnapshot>,
+    pub encounters: Vec<EncounterSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RankSnapshot {
+    pub rank: f32,
+    pub last_score: i64,
+    pub last_power: i64,
+    pub last_lives: i64,
+    pub last_bombs: i64,
+    pub last_dead: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RngSnapshot {
+    pub seed: i64,
+    // (state, increment) of each stream
+    pub streams: Vec<(u64, u64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TimeScaleSnapshot {
+    pub slow_motion: f32,
+    // Restoring during a hit-stop finishes it rather than resuming at full speed
+    pub hit_stop_remaining: f32,
+}
+
+// The complete simulation state
+#[derive(Serialize, Deserialize)]
+pub struct SaveState {
+    // Must stay the first field, it is read on its own by `decode`
+    pub version: u32,
+    // Player 1 first
+    pub players: Vec<PlayerSnapshot>,
+    pub bullets: BulletManagerSnapshot,
+    pub encounters: EncounterManagerSnapshot,
+    pub rank: RankSnapshot,
+    pub rng: RngSnapshot,
+    pub time_scale: TimeScaleSnapshot,
+}
+
+impl SaveState {
+    pub fn encode(&self) -> Result<Vec<u8>, SaveStateError> {
+        bincode::serialize(self).map_err(|error| SaveStateError::Encoding(error.to_string()))
+    }
+
+    pub fn decode(data: &[u8]) -> Result<SaveState, SaveStateError> {
+        // Check the version before reading the rest, which may have a different layout
+        let version: u32 = bincode::deserialize(data)
+            .map_err(|error| SaveStateError::Encoding(error.to_string()))?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::Version {
+                found: version,
+                expected: SAVE_STATE_VERSION,
+            });
+        }
+
+        bincode::deserialize(data).map_err(|error| SaveStateError::Encoding(error.to_string()))
+    }
+}
+
+// Captures and restores the simulation state of the Players, BulletManager,
+// EncounterManager, Rank, Rng and TimeScale, for checkpoints, quick-saves and crash recovery.
+// Must not be called from signal handlers, as those run during the ticks of these nodes
+#[derive(NativeClass, Default)]
+#[inherit(Node)]
+pub struct SaveStates {
+    #[property]
+    bullet_manager_path: NodePath,
+    // The players are looked up from the encounter manager
+    #[property]
+    encounter_manager_path: NodePath,
+    #[property]
+    rank_path: NodePath,
+    #[property]
+    rng_path: NodePath,
+    #[property]
+    time_scale_path: NodePath,
+    players: Option<Vec<TInstance<'static, Player, Shared>>>,
+    bullet_manager: Option<TInstance<'static, BulletManager, Shared>>,
+    encounter_manager: Option<TInstance<'static, EncounterManager, Shared>>,
+    rank: Option<TInstance<'static, Rank, Shared>>,
+    rng: Option<TInstance<'static, Rng, Shared>>,
+    time_scale: Option<TInstance<'static, TimeScale, Shared>>,
+
+    // In-memory slot used by quick_save and quick_load
+    quick_save: Option<Vec<u8>>,
+}
+
+#[methods]
+impl SaveStates {
+    fn new(_owner: &Node) -> Self {
+        Self {
+            bullet_manager_path: NodePath::from_str("../Bullets"),
+            encounter_manager_path: NodePath::from_str("../Encounters"),
+            rank_path: NodePath::from_str("../Rank"),
+            rng_path: NodePath::from_str("../Rng"),
+            time_scale_path: NodePath::from_str("../Time"),
+            ..Default::default()
+        }
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Node) {
+        self.bullet_manager =
+            node_paths::fetch_instance(owner, &self.bullet_manager_path, "BulletManager");
+        self.encounter_manager = node_paths::fetch_instance(
+            owner,
+            &self.encounter_manager_path,
+            "EncounterManager",
+        );
+        self.players = encounter_manager::players(&self.encounter_manager);
+        self.rank = node_paths::fetch_instance(owner, &self.rank_path, "Rank");
+        self.rng = node_paths::fetch_instance(owner, &self.rng_path, "Rng");
+        self.time_scale = node_paths::fetch_instance(owner, &self.time_scale_path, "TimeScale");
+    }
+
+    // Captures the current state of every node
+    pub fn capture(&self) -> Option<SaveState> {
+        let (players, bullet_manager, encounter_manager, rank, rng, time_scale) = match (
+            &self.players,
+            &self.bullet_manager,
+            &self.encounter_manager,
+            &self.rank,
+            &self.rng,
+            &self.time_scale,
+        ) {
+            (Some(u), Some(v), Some(w), Some(x), Some(y), Some(z)) => (u, v, w, x, y, z),
+            // Missing nodes have already been reported by _ready
+            _ => return None,
+        };
+
+        Some(SaveState {
+            version: SAVE_STATE_VERSION,
+            players: players
+                .iter()
+                .map(|player| {
+                    player
+                        .map(|x: &Player, node: TRef<Node2D>| x.snapshot(node.as_ref()))
+                        .unwrap()
+                })
+                .collect(),
+            bullets: bullet_manager
+                .map(|x: &BulletManager, _node: TRef<Node2D>| x.snapshot())
+                .unwrap(),
+            encounters: encounter_manager
+                .map(|x: &EncounterManager, _node: TRef<Node2D>| x.snapshot())
+                .unwrap(),
+            rank: rank.map(|x: &Rank, _node: TRef<Node>| x.snapshot()).unwrap(),
+            rng: rng.map(|x: &Rng, _node: TRef<Node>| x.snapshot()).unwrap(),
+            time_scale: time_scale
+                .map(|x: &TimeScale, _node: TRef<Node>| x.snapshot())
+                .unwrap(),
+        })
+    }
+
+    // Puts every node back into a captured state.
+    // The player count, ships and random streams are checked before anything
+    // changes. After them, only the encounters can reject a state, and they undo
+    // their own changes when they do, so they go first and the other nodes are
+    // left untouched
+    pub fn apply(&self, state: &SaveState) -> Result<(), SaveStateError> {
+        let (players, bullet_manager, encounter_manager, rank, rng, time_scale) = match (
+            &self.players,
+            &self.bullet_manager,
+            &self.encounter_manager,
+            &self.rank,
+            &self.rng,
+            &self.time_scale,
+        ) {
+            (Some(u), Some(v), Some(w), Some(x), Some(y), Some(z)) => (u, v, w, x, y, z),
+            _ => return Err(SaveStateError::Mismatch("Missing nodes".to_string())),
+        };
+
+        if state.players.len() != players.len() {
+            return Err(SaveStateError::Mismatch(format!(
+                "Saved with {} players, playing with {}",
+                state.players.len(),
+                players.len()
+            )));
+        }
+        for (player, snapshot) in players.iter().zip(&state.players) {
+            let ship = player.map(|x: &Player, _node: TRef<Node2D>| x.ship()).unwrap();
+            if snapshot.ship != ship {
+                return Err(SaveStateError::Mismatch(format!(
+                    "Saved with the {} ship, playing the {}",
+                    snapshot.ship.name(),
+                    ship.name()
+                )));
+            }
+        }
+
+        rng.map(|x: &Rng, _node: TRef<Node>| x.check(&state.rng))
+            .unwrap()?;
+
+        encounter_manager
+            .map_mut(|x: &mut EncounterManager, _node: TRef<Node2D>| {
+                x.restore(&state.encounters)
+            })
+            .unwrap()?;
+        for (player, snapshot) in players.iter().zip(&state.players) {
+            player
+                .map_mut(|x: &mut Player, node: TRef<Node2D>| x.restore(node.as_ref(), snapshot))
+                .unwrap();
+        }
+        bullet_manager
+            .map_mut(|x: &mut BulletManager, node: TRef<Node2D>| {
+                x.restore(node.as_ref(), &state.bullets)
+            })
+            .unwrap();
+        rank.map_mut(|x: &mut Rank, _node: TRef<Node>| x.restore(&state.rank))
+            .unwrap();
+        rng.map_mut(|x: &mut Rng, _node: TRef<Node>| x.restore(&state.rng))
+            .unwrap()?;
+        time_scale
+            .map_mut(|x: &mut TimeScale, _node: TRef<Node>| x.restore(&state.time_scale))
+            .unwrap();
+        Ok(())
+    }
+
+    // Returns the encoded state, empty if it couldn't be captured
+    #[export]
+    fn save(&self, _owner: &Node) -> ByteArray {
+        match self.capture().map(|state| state.encode()) {
+            Some(Ok(data)) => ByteArray::from_vec(data),
+            Some(Err(error)) => {
+                godot_error!("{error}");
+                ByteArray::new()
+            }
+            None => ByteArray::new(),
+        }
+    }
+
+    // Restores an encoded state, returning false (and changing nothing) if it is invalid
+    #[export]
+    fn load(&self, _owner: &Node, data: ByteArray) -> bool {
+        let result = SaveState::decode(&data.read()).and_then(|state| self.apply(&state));
+        if let Err(error) = &result {
+            godot_error!("{error}");
+        }
+        result.is_ok()
+    }
+
+    #[export]
+    fn quick_save(&mut self, _owner: &Node) -> bool {
+        match self.capture().map(|state| state.encode()) {
+            Some(Ok(data)) => {
+                self.quick_save = Some(data);
+                true
+            }
+            Some(Err(error)) => {
+                godot_error!("{error}");
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Returns false if nothing has been quick-saved
+    #[export]
+    fn quick_load(&self, _owner: &Node) -> bool {
+        let data = match &self.quick_save {
+            Some(data) => data,
+            None => return false,
+        };
+        let result = SaveState::decode(data).and_then(|state| self.apply(&state));
+        if let Err(error) = &result {
+            godot_error!("{error}");
+        }
+        result.is_ok()
+    }
+
+    // Writes the state to a file, accepting Godot paths such as "user://crash.sav"
+    #[export]
+    fn save_to_file(&self, _owner: &Node, path: String) -> bool {
+        let result = match self.capture() {
+            Some(state) => state.encode().and_then(|data| {
+                std::fs::write(SaveStates::globalize(&path), data)
+                    .map_err(|error| SaveStateError::Io(error.to_string()))
+            }),
+            None => return false,
+        };
+        if let Err(error) = &result {
+            godot_error!("{error}");
+        }
+        result.is_ok()
+    }
+
+    #[export]
+    fn load_from_file(&self, _owner: &Node, path: String) -> bool {
+        let result = std::fs::read(SaveStates::globalize(&path))
+            .map_err(|error| SaveStateError::Io(error.to_string()))
+            .and_then(|data| SaveState::decode(&data))
+            .and_then(|state| self.apply(&state));
+        if let Err(error) = &result {
+            godot_error!("{error}");
+        }
+        result.is_ok()
+    }
+
+    // Converts "res://" and "user://" paths to paths on disk
+    fn globalize(path: &str) -> String {
+        ProjectSettings::godot_singleton()
+            .globalize_path(path)
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bullet(despawn_frames: Option<u32>) -> BulletSnapshot {
+        BulletSnapshot {
+            kind: "orb_bullet".to_string(),
+            position: (120.0, 40.0),
+            dx: 0.0,
+            dy: 60.0,
+            grazed: false,
+            age: 1.5,
+            burst: None,
+            orbit: None,
+            group: Some(2),
+            tint: (1.0, 0.5, 0.5, 1.0),
+            scale: 1.5,
+            frame: 2,
+            homing: Some(3.0),
+            despawn_frames,
+        }
+    }
+
+    fn state(version: u32) -> SaveState {
+        SaveState {
+            version,
+            players: vec![PlayerSnapshot {
+                ship: Ship::Needle,
+                position: (240.0, 220.0),
+                time: 5000.0,
+                last_attack: 4950,
+                last_hit: -1,
+                dead: false,
+                lives: 2,
+                bombs: 3,
+                score: 1200,
+                power: 40,
+                graze: 7,
+                invulnerability_anim: 0,
+                options: Some(OptionsSnapshot {
+                    positions: vec![(220.0, 230.0), (260.0, 230.0)],
+                    trail: vec![],
+                    angle: 0.5,
+                    last_attack: 4900,
+                }),
+            }],
+            bullets: BulletManagerSnapshot {
+                bullets: vec![bullet(None), bullet(Some(3))],
+                lasers: vec![],
+                next_group: 3,
+            },
+            encounters: EncounterManagerSnapshot {
+                difficulty: Difficulty::Hard,
+                stage_time: 5000.0,
+                stage_cleared: false,
+                lifecycles: vec![LifecycleSnapshot {
+                    state: EncounterState::Active,
+                    time_in_state: 250.0,
+                }],
+                encounters: vec![EncounterSnapshot::FirstBoss(FirstBossSnapshot {
+                    health: 80,
+                    time: 2500.0,
+                })],
+            },
+            rank: RankSnapshot {
+                rank: 0.25,
+                last_score: 1200,
+                last_power: 40,
+                last_lives: 2,
+                last_bombs: 3,
+                last_dead: false,
+            },
+            rng: RngSnapshot {
+                seed: 42,
+                streams: vec![(1, 1), (2, 3), (3, 5)],
+            },
+            time_scale: TimeScaleSnapshot {
+                slow_motion: 1.0,
+                hit_stop_remaining: 0.25,
+            },
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = state(SAVE_STATE_VERSION).encode().unwrap();
+        let decoded = SaveState::decode(&data).unwrap();
+
+        assert_eq!(decoded.version, SAVE_STATE_VERSION);
+        assert_eq!(decoded.players.len(), 1);
+        assert_eq!(decoded.players[0].score, 1200);
+        assert_eq!(decoded.players[0].ship, Ship::Needle);
+        assert_eq!(decoded.bullets.bullets[0].despawn_frames, None);
+        assert_eq!(decoded.bullets.bullets[1].despawn_frames, Some(3));
+        assert_eq!(decoded.encounters.stage_time, 5000.0);
+        assert_eq!(decoded.encounters.difficulty, Difficulty::Hard);
+        assert_eq!(decoded.time_scale.hit_stop_remaining, 0.25);
+        assert_eq!(decoded.rng.streams.len(), 3);
+        // Nothing is lost along the way
+        assert_eq!(decoded.encode().unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let data = state(SAVE_STATE_VERSION + 1).encode().unwrap();
+        match SaveState::decode(&data) {
+            Err(SaveStateError::Version { found, expected }) => {
+                assert_eq!(found, SAVE_STATE_VERSION + 1);
+                assert_eq!(expected, SAVE_STATE_VERSION);
+            }
+            _ => panic!("Expected a version error"),
+        }
+    }
+
+    #[test]
+    fn rejects_version_1() {
+        // Saved before save states held several players and the time scale
+        let data = state(1).encode().unwrap();
+        match SaveState::decode(&data) {
+            Err(SaveStateError::Version { found, expected }) => {
+                assert_eq!(found, 1);
+                assert_eq!(expected, 2);
+            }
+            _ => panic!("Expected a version error"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = state(SAVE_STATE_VERSION).encode().unwrap();
+        assert!(matches!(
+            SaveState::decode(&data[..data.len() / 2]),
+            Err(SaveStateError::Encoding(_))
+        ));
+        assert!(matches!(
+            SaveState::decode(&[]),
+            Err(SaveStateError::Encoding(_))
+        ));
+    }
+}
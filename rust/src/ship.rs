@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+
+// Ship selected at the start of a run
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub enum Ship {
+    // Wide spread of shots, the original ship
+    #[default]
+    Spread,
+    // Narrow, fast firing shots with a smaller hitbox
+    Needle,
+}
+
+impl Ship {
+    pub const ALL: [Ship; 2] = [Ship::Spread, Ship::Needle];
+
+    // Parses the names used by properties, "spread" or "needle"
+    pub fn from_name(name: &str) -> Option<Ship> {
+        match name {
+            "spread" => Some(Ship::Spread),
+            "needle" => Some(Ship::Needle),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ship::Spread => "spread",
+            Ship::Needle => "needle",
+        }
+    }
+
+    pub fn definition(&self) -> &'static ShipDefinition {
+        match self {
+            Ship::Spread => &SPREAD,
+            Ship::Needle => &NEEDLE,
+        }
+    }
+}
+
+// One bullet of a volley, relative to the ship
+pub struct Shot {
+    pub kind: &'static str,
+    pub offset: (f32, f32),
+    // Sideways velocity, the bullet always moves up at the weapon's speed
+    pub dx: f32,
+}
+
+// Volley fired at a fixed rate while shooting
+pub struct Weapon {
+    pub shots: &'static [Shot],
+    pub speed: f32,
+    pub timeout_ms: i64,
+}
+
+// What a bomb does to enemy bullets, both also grant invulnerability
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BombType {
+    // Cancels every enemy bullet into a score item
+    Items,
+    // Cancels every enemy bullet without leaving anything behind
+    Clear,
+}
+
+pub struct ShipDefinition {
+    // Movement speed (px/sec)
+    pub speed: f32,
+    // Movement speed while focused (px/sec)
+    pub focus_speed: f32,
+    pub hitbox_radius: f32,
+    // Fired while shooting
+    pub primary: Weapon,
+    // Fired instead of the primary while focused
+    pub secondary: Weapon,
+    pub bomb: BombType,
+}
+
+// The original ship's volley, it has no separate focused weapon
+const SPREAD_WEAPON: Weapon = Weapon {
+    shots: &[
+        Shot {
+            kind: "player_primary_03",
+            offset: (-12.0, -6.0),
+            dx: -20.0,
+        },
+        Shot {
+            kind: "player_primary_02",
+            offset: (-10.0, -9.0),
+            dx: -10.0,
+        },
+        Shot {
+            kind: "player_primary_01",
+            offset: (-4.0, -19.0),
+            dx: 0.0,
+        },
+        Shot {
+            kind: "player_primary_02",
+            offset: (5.0, -9.0),
+            dx: 10.0,
+        },
+        Shot {
+            kind: "player_primary_03",
+            offset: (11.0, -6.0),
+            dx: 20.0,
+        },
+    ],
+    speed: 300.0,
+    timeout_ms: 90,
+};
+
+// Plays exactly like the Player did before ships, focusing changes nothing
+const SPREAD: ShipDefinition = ShipDefinition {
+    speed: 120.0,
+    focus_speed: 120.0,
+    hitbox_radius: 4.0,
+    primary: SPREAD_WEAPON,
+    secondary: SPREAD_WEAPON,
+    bomb: BombType::Items,
+};
+
+const NEEDLE: ShipDefinition = ShipDefinition {
+    speed: 140.0,
+    focus_speed: 55.0,
+    hitbox_radius: 3.0,
+    primary: Weapon {
+        shots: &[
+            Shot {
+                kind: "player_primary_01",
+                offset: (-8.0, -15.0),
+                dx: -5.0,
+            },
+            Shot {
+                kind: "player_primary_01",
+                offset: (-4.0, -19.0),
+                dx: 0.0,
+            },
+            Shot {
+                kind: "player_primary_01",
+                offset: (2.0, -15.0),
+                dx: 5.0,
+            },
+        ],
+        speed: 420.0,
+        timeout_ms: 60,
+    },
+    secondary: Weapon {
+        shots: &[
+            Shot {
+                kind: "player_primary_01",
+                offset: (-6.0, -15.0),
+                dx: 0.0,
+            },
+            Shot {
+                kind: "player_primary_01",
+                offset: (-4.0, -19.0),
+                dx: 0.0,
+            },
+            Shot {
+                kind: "player_primary_01",
+                offset: (-2.0, -15.0),
+                dx: 0.0,
+            },
+        ],
+        speed: 480.0,
+        timeout_ms: 50,
+    },
+    bomb: BombType::Clear,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for ship in Ship::ALL {
+            assert_eq!(Ship::from_name(ship.name()), Some(ship));
+        }
+        assert_eq!(Ship::from_name("laser"), None);
+    }
+
+    #[test]
+    fn default_is_the_original_ship() {
+        let ship = Ship::default();
+        assert_eq!(ship, Ship::Spread);
+        assert_eq!(ship.name(), "spread");
+        // Focusing changes nothing
+        let definition = ship.definition();
+        assert_eq!(definition.speed, definition.focus_speed);
+        assert_eq!(definition.primary.shots.len(), 5);
+        assert_eq!(definition.secondary.shots.len(), 5);
+        assert_eq!(definition.bomb, BombType::Items);
+    }
+}
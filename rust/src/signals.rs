@@ -0,0 +1,13 @@
+use gdnative::prelude::*;
+
+// Emits `signal` on `owner` at the end of the frame rather than straight away.
+// Signals are raised while nodes are borrowed by their own tick, and usually
+// by the tick of the node calling into them, so handlers run immediately
+// would fail to borrow any of those nodes when calling back into them
+pub fn emit_deferred(owner: &Object, signal: &str, args: &[Variant]) {
+    let mut varargs = vec![signal.to_variant()];
+    varargs.extend_from_slice(args);
+    unsafe {
+        owner.call_deferred("emit_signal", &varargs);
+    }
+}
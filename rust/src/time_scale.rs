@@ -0,0 +1,167 @@
+use gdnative::prelude::*;
+
+use crate::save_state::TimeScaleSnapshot;
+
+// Scales the deltatime of every Rust `_process`, providing pause,
+// global slow-motion and short hit-stop freezes.
+// Must be processed before the nodes using it (placed above them in the tree)
+#[derive(NativeClass, Default)]
+#[inherit(Node)]
+pub struct TimeScale {
+    // Speed of the game while not paused or frozen (1.0 = normal speed)
+    #[property(default = 1.0)]
+    slow_motion: f32,
+
+    paused: bool,
+    // Real time left in the current hit-stop (secs)
+    hit_stop_remaining: f32,
+}
+
+#[methods]
+impl TimeScale {
+    fn new(_owner: &Node) -> Self {
+        Self {
+            slow_motion: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[export]
+    fn _process(&mut self, _owner: &Node, deltatime: f32) {
+        self.advance(deltatime);
+    }
+
+    // Counts down the current hit-stop by `deltatime` of real time (secs)
+    fn advance(&mut self, deltatime: f32) {
+        // Hit-stops run on real time, but wait while paused
+        if !self.paused {
+            self.hit_stop_remaining = (self.hit_stop_remaining - deltatime).max(0.0);
+        }
+    }
+
+    // Freezes everything until `resume`, timers and cooldowns are kept as they are
+    #[export]
+    pub fn pause(&mut self, _owner: &Node) {
+        self.paused = true;
+    }
+
+    #[export]
+    pub fn resume(&mut self, _owner: &Node) {
+        self.paused = false;
+    }
+
+    #[export]
+    pub fn is_paused(&self, _owner: &Node) -> bool {
+        self.paused
+    }
+
+    #[export]
+    pub fn set_slow_motion(&mut self, _owner: &Node, scale: f32) {
+        self.slow_motion = scale.max(0.0);
+    }
+
+    // Freezes the game for `ms` of real time, extending any current hit-stop
+    #[export]
+    pub fn hit_stop(&mut self, _owner: &Node, ms: i64) {
+        self.hit_stop_remaining = self.hit_stop_remaining.max(ms as f32 / 1000.0);
+    }
+
+    // Multiplier for deltatime this frame
+    pub fn scale(&self) -> f32 {
+        if self.paused || self.hit_stop_remaining > 0.0 {
+            0.0
+        } else {
+            self.slow_motion
+        }
+    }
+
+    // Pausing is left to whoever restores the state
+    pub fn snapshot(&self) -> TimeScaleSnapshot {
+        TimeScaleSnapshot {
+            slow_motion: self.slow_motion,
+            hit_stop_remaining: self.hit_stop_remaining,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &TimeScaleSnapshot) {
+        self.slow_motion = snapshot.slow_motion;
+        self.hit_stop_remaining = snapshot.hit_stop_remaining;
+    }
+}
+
+// Applies the time scale to a deltatime, passing it through unchanged
+// when no TimeScale was found
+pub fn scaled(time_scale: &Option<TInstance<'static, TimeScale, Shared>>, deltatime: f32) -> f32 {
+    match time_scale {
+        Some(time_scale) => {
+            deltatime
+                * time_scale
+                    .map(|x: &TimeScale, _node: TRef<Node>| x.scale())
+                    .unwrap()
+        }
+        None => deltatime,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_scale() -> TimeScale {
+        TimeScale {
+            slow_motion: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pause_and_slow_motion() {
+        let mut time = time_scale();
+        assert_eq!(time.scale(), 1.0);
+        time.slow_motion = 0.5;
+        assert_eq!(time.scale(), 0.5);
+        time.paused = true;
+        assert_eq!(time.scale(), 0.0);
+        time.paused = false;
+        assert_eq!(time.scale(), 0.5);
+    }
+
+    #[test]
+    fn hit_stop_freezes_for_its_duration() {
+        let mut time = time_scale();
+        time.hit_stop_remaining = 0.25;
+        time.advance(0.125);
+        assert_eq!(time.scale(), 0.0);
+        time.advance(0.125);
+        assert_eq!(time.scale(), 1.0);
+        // Never goes below 0
+        time.advance(1.0);
+        assert_eq!(time.hit_stop_remaining, 0.0);
+    }
+
+    #[test]
+    fn hit_stop_waits_while_paused() {
+        let mut time = time_scale();
+        time.hit_stop_remaining = 0.25;
+        time.paused = true;
+        time.advance(1.0);
+        assert_eq!(time.hit_stop_remaining, 0.25);
+        time.paused = false;
+        time.advance(0.25);
+        assert_eq!(time.scale(), 1.0);
+    }
+
+    #[test]
+    fn restores_a_hit_stop() {
+        let mut time = time_scale();
+        time.slow_motion = 0.5;
+        time.hit_stop_remaining = 0.25;
+        let snapshot = time.snapshot();
+
+        let mut restored = time_scale();
+        restored.restore(&snapshot);
+        assert_eq!(restored.scale(), 0.0);
+        restored.advance(0.25);
+        assert_eq!(restored.scale(), 0.5);
+    }
+}